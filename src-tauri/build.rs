@@ -1,6 +1,9 @@
 extern crate winres;
 fn main() {
     tauri_build::build();
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("windows") {
+        return;
+    }
     let mut res = winres::WindowsResource::new();
     res.set_manifest(
         r#"
@@ -0,0 +1,202 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use btleplug::api::PeripheralProperties;
+use uuid::Uuid;
+
+/// Bytes in front of each payload when the header is on: the address (6),
+/// RSSI in dBm (1, 127 when unknown), milliseconds since the Unix epoch (8,
+/// little-endian) and the payload length (2, little-endian).
+pub const HEADER_LEN: usize = 17;
+
+const UNKNOWN_RSSI: i8 = 127;
+
+/// Which part of the advertisement carries the readings.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum AdvertisementSource {
+    #[default]
+    ManufacturerData,
+    ServiceData,
+}
+
+/// Forwards advertisement payloads to the bridge instead of requiring a
+/// connection, for devices that only broadcast.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AdvertisementConfig {
+    #[serde(default)]
+    pub source: AdvertisementSource,
+    /// Only manufacturer data with this company identifier.
+    #[serde(default)]
+    pub company_id: Option<u16>,
+    /// Only service data of this service.
+    #[serde(default)]
+    pub service_uuid: Option<Uuid>,
+    /// Only these addresses; every device passing the name filter when empty.
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    /// Precede each payload with a [`HEADER_LEN`] byte header.
+    #[serde(default)]
+    pub header: bool,
+    /// A payload equal to the last one forwarded for the same device within
+    /// this window is dropped; 0 forwards every advertisement.
+    #[serde(default = "default_dedup_window_ms")]
+    pub dedup_window_ms: u64,
+}
+
+fn default_dedup_window_ms() -> u64 {
+    1000
+}
+
+/// The data fields of one advertisement.
+pub enum Advertisement<'a> {
+    Manufacturer(&'a HashMap<u16, Vec<u8>>),
+    Service(&'a HashMap<Uuid, Vec<u8>>),
+}
+
+impl AdvertisementConfig {
+    /// Whether advertisements of `address` are forwarded; `known` tells if
+    /// the device passed the name filter.
+    pub fn accepts(&self, address: &str, known: bool) -> bool {
+        if self.addresses.is_empty() {
+            return known;
+        }
+        self.addresses
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(address))
+    }
+
+    /// The payloads selected from `advertisement`, keyed by company
+    /// identifier or service UUID.
+    pub fn payloads<'a>(&self, advertisement: Advertisement<'a>) -> Vec<(u128, &'a [u8])> {
+        match (self.source, advertisement) {
+            (AdvertisementSource::ManufacturerData, Advertisement::Manufacturer(data)) => data
+                .iter()
+                .filter(|(company, _)| self.company_id.map_or(true, |id| id == **company))
+                .map(|(company, payload)| (*company as u128, payload.as_slice()))
+                .collect(),
+            (AdvertisementSource::ServiceData, Advertisement::Service(data)) => data
+                .iter()
+                .filter(|(uuid, _)| self.service_uuid.map_or(true, |id| id == **uuid))
+                .map(|(uuid, payload)| (uuid.as_u128(), payload.as_slice()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// What is written to the bridge for one payload.
+    pub fn encode(
+        &self,
+        address: [u8; 6],
+        rssi: Option<i16>,
+        timestamp: u64,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        if !self.header {
+            return payload.to_vec();
+        }
+        let rssi = rssi
+            .map(|rssi| rssi.clamp(i8::MIN as i16, i8::MAX as i16) as i8)
+            .unwrap_or(UNKNOWN_RSSI);
+        let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+        data.extend_from_slice(&address);
+        data.push(rssi as u8);
+        data.extend_from_slice(&timestamp.to_le_bytes());
+        data.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ManufacturerData {
+    pub company_id: u16,
+    pub data: Vec<u8>,
+    pub hex: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ServiceData {
+    pub uuid: Uuid,
+    pub data: Vec<u8>,
+    pub hex: String,
+}
+
+/// The latest advertisement of a device, with hex views of the data fields.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AdvertisementData {
+    pub id: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub tx_power: Option<i16>,
+    pub services: Vec<Uuid>,
+    /// Sorted by company identifier.
+    pub manufacturer_data: Vec<ManufacturerData>,
+    /// Sorted by service UUID.
+    pub service_data: Vec<ServiceData>,
+}
+
+impl AdvertisementData {
+    pub fn from_properties(id: String, properties: &PeripheralProperties) -> Self {
+        let mut manufacturer_data: Vec<ManufacturerData> = properties
+            .manufacturer_data
+            .iter()
+            .map(|(company_id, data)| ManufacturerData {
+                company_id: *company_id,
+                data: data.clone(),
+                hex: hex(data),
+            })
+            .collect();
+        manufacturer_data.sort_by_key(|field| field.company_id);
+        let mut service_data: Vec<ServiceData> = properties
+            .service_data
+            .iter()
+            .map(|(uuid, data)| ServiceData {
+                uuid: *uuid,
+                data: data.clone(),
+                hex: hex(data),
+            })
+            .collect();
+        service_data.sort_by_key(|field| field.uuid);
+
+        Self {
+            id,
+            name: properties.local_name.clone(),
+            rssi: properties.rssi,
+            tx_power: properties.tx_power_level,
+            services: properties.services.clone(),
+            manufacturer_data,
+            service_data,
+        }
+    }
+}
+
+/// Remembers the last payload forwarded per device and field.
+#[derive(Default)]
+pub struct Deduplicator {
+    last: HashMap<(String, u128), (Vec<u8>, Instant)>,
+}
+
+impl Deduplicator {
+    /// Whether `payload` should be forwarded, recording it if so.
+    pub fn check(&mut self, id: &str, key: u128, payload: &[u8], window: Duration) -> bool {
+        let now = Instant::now();
+        match self.last.entry((id.to_string(), key)) {
+            Entry::Occupied(mut last) => {
+                let (data, at) = last.get();
+                if data == payload && now.duration_since(*at) < window {
+                    return false;
+                }
+                last.insert((payload.to_vec(), now));
+            }
+            Entry::Vacant(slot) => {
+                slot.insert((payload.to_vec(), now));
+            }
+        }
+        true
+    }
+}
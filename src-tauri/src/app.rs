@@ -1,62 +1,970 @@
 use crate::{
     ble::{BleEvent, BleManager, Device},
+    bus::{EventBus, Received, Topic},
+    channel::{self, ChannelMode, PRIMARY_TAG},
+    crc::{BadFramePolicy, CrcCheckers, CrcVerdict},
+    decoder::{DecodedOutput, DecoderConfig, FrameDecoder},
+    framing::{Frame, Framing, Reassemblers},
+    inspector::FrameInspectors,
     port::PortManager,
+    settings::{self, FieldError, KnownDevice, Settings},
+    sink::{spawn_sink, spawn_tcp_sink, RawSink, SinkConfig, SinkData, SinkInfo, SinkRegistry},
 };
 use btleplug::api::ValueNotification;
-use log::error;
-use std::{path::PathBuf, sync::Arc};
-use tokio::sync::{mpsc, mpsc::Sender, Mutex};
+use log::{debug, error};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{error::Error, fs::OpenOptions, io::Write, path::PathBuf, sync::Arc};
+use tokio::sync::Mutex;
+
+const PORT_SINK: &str = "port";
+const DECODED_SINK: &str = "decoded";
+
+/// Framing of the known devices that override it, by device id.
+fn known_framing(settings: &Settings) -> HashMap<String, Framing> {
+    settings
+        .known_devices
+        .iter()
+        .filter_map(|known| {
+            let framing = known.framing.clone()?;
+            Some((known.address.to_uppercase(), framing))
+        })
+        .collect()
+}
+
+/// Bad frame policy of the known devices that override it, by device id.
+fn known_crc_policies(settings: &Settings) -> HashMap<String, BadFramePolicy> {
+    settings
+        .known_devices
+        .iter()
+        .filter_map(|known| {
+            let policy = known.crc_policy.clone()?;
+            Some((known.address.to_uppercase(), policy))
+        })
+        .collect()
+}
+
+/// Name of the sink writing a device's data to its own port.
+fn device_port_sink(id: &str) -> String {
+    format!("device:{}", id)
+}
+
+/// Name of the sink decoding a device's frames with its own decoder.
+fn device_decoder_sink(id: &str) -> String {
+    format!("decoded:{}", id)
+}
+
+fn channel_sink(id: &str, channel: &str) -> String {
+    format!("channel:{}:{}", id, channel)
+}
 
 pub struct AppState {
+    pub bus: EventBus,
     pub ble_manager: BleManager,
-    pub port_manager: PortManager,
-    pub other_port: Option<String>,
+    pub port_manager: Mutex<PortManager>,
+    pub other_port: Mutex<Option<String>>,
+    pub inspectors: Arc<Mutex<FrameInspectors>>,
+    pub decoded_port: Mutex<Option<String>>,
+    /// com0com pair of the raw port, unless bridging to another serial port.
+    bridged_pair: Mutex<Option<u32>>,
+    pub reassemblers: Arc<Mutex<Reassemblers>>,
+    pub crc_checkers: Arc<Mutex<CrcCheckers>>,
+    pub sinks: Arc<Mutex<SinkRegistry>>,
+    pub bridge_counters: Arc<Mutex<HashMap<String, BridgeCounters>>>,
+    /// Events the bridge fell too far behind to process.
+    pub missed_events: Arc<AtomicU64>,
+    pub settings: Mutex<Settings>,
+    settings_path: Option<PathBuf>,
+    /// Held while settings are applied, which may reopen ports.
+    updating: Mutex<()>,
+}
+
+/// What the bridge handed to the sinks for one device.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BridgeCounters {
+    pub frames: u64,
+    pub bytes: u64,
+}
+
+/// The parts of the state notifications pass through on their way to the
+/// sinks.
+struct Bridge {
+    reassemblers: Arc<Mutex<Reassemblers>>,
+    crc_checkers: Arc<Mutex<CrcCheckers>>,
+    inspectors: Arc<Mutex<FrameInspectors>>,
+    sinks: Arc<Mutex<SinkRegistry>>,
+    counters: Arc<Mutex<HashMap<String, BridgeCounters>>>,
+    bus: EventBus,
+}
+
+impl Bridge {
+    /// Frames data of one of the device's characteristics and hands the
+    /// frames to the sinks.
+    ///
+    /// Multiplexed data comes with its channel `tag`. Each channel is framed
+    /// and counted on its own, and the header only goes in front of finished
+    /// frames and markers.
+    async fn push(&self, id: &str, tag: Option<u8>, data: &[u8]) {
+        let channel = tag.unwrap_or(PRIMARY_TAG);
+        let frames = self.reassemblers.lock().await.push(id, channel, data);
+        let mut bridged = BridgeCounters::default();
+
+        for frame in frames {
+            let verdict = self.crc_checkers.lock().await.check(id, &frame.payload);
+            let mut markers = Vec::new();
+            if let CrcVerdict::Invalid(policy, crc_error) = verdict {
+                self.bus.publish(BleEvent::CrcError(crc_error));
+                match policy {
+                    BadFramePolicy::Forward => {}
+                    BadFramePolicy::Drop => continue,
+                    BadFramePolicy::Mark(marker) => markers.push(marker),
+                }
+            }
+
+            let loss = {
+                let mut inspectors = self.inspectors.lock().await;
+                inspectors.inspect(id, channel, &frame.payload).map(|loss| {
+                    if loss.missing > 0 {
+                        markers.extend(inspectors.gap_marker().map(|m| m.to_vec()));
+                    }
+                    loss
+                })
+            };
+
+            if let Some(loss) = loss {
+                self.bus.publish(BleEvent::FrameLoss(loss));
+            }
+
+            let (markers, frame) = match tag {
+                Some(tag) => (
+                    markers
+                        .iter()
+                        .map(|marker| channel::tagged(tag, marker))
+                        .collect(),
+                    Frame {
+                        raw: channel::tagged(tag, &frame.raw),
+                        payload: frame.payload,
+                    },
+                ),
+                None => (markers, frame),
+            };
+
+            bridged.frames += 1;
+            bridged.bytes += frame.raw.len() as u64;
+            bridged.bytes += markers.iter().map(|m| m.len() as u64).sum::<u64>();
+
+            let sinks = self.sinks.lock().await;
+            for bytes in markers {
+                sinks.send(SinkData::Marker {
+                    id: id.to_string(),
+                    bytes,
+                });
+            }
+            sinks.send(SinkData::Frame {
+                id: id.to_string(),
+                frame,
+            });
+        }
+
+        if bridged.frames > 0 {
+            let mut counters = self.counters.lock().await;
+            let counters = counters.entry(id.to_string()).or_default();
+            counters.frames += bridged.frames;
+            counters.bytes += bridged.bytes;
+        }
+    }
 }
 
-pub type AppStateType = Arc<Mutex<AppState>>;
+/// Shared by every command handler. Each part is locked on its own, so a slow
+/// BLE or port operation only holds up callers that need the same part.
+pub type AppStateType = Arc<AppState>;
 
 impl AppState {
     pub async fn new() -> AppStateType {
-        let ble_manager = BleManager::new().await.unwrap();
-        let port_manager = PortManager::new().await;
+        Self::with_settings_path(settings::default_path())
+            .await
+            .unwrap()
+    }
 
-        Arc::new(Mutex::new(Self {
+    /// Loads settings from `settings_path` instead of the default location;
+    /// fails when there is no Bluetooth adapter.
+    pub async fn with_settings_path(
+        settings_path: Option<PathBuf>,
+    ) -> Result<AppStateType, Box<dyn Error>> {
+        let mut settings_path = settings_path;
+        let settings = match &settings_path {
+            Some(path) => {
+                debug!("Loading settings from {}", path.display());
+                match Settings::load(path) {
+                    Ok(settings) => settings,
+                    Err(err) if err.is::<settings::NewerVersion>() => {
+                        // Keep the file for the newer app, and don't save over it.
+                        error!("Not using {}, using defaults: {}", path.display(), err);
+                        settings_path = None;
+                        Settings::default()
+                    }
+                    Err(err) => {
+                        error!("Error loading settings, using defaults: {}", err);
+                        match Settings::set_aside(path) {
+                            Ok(bad) => error!("Moved the settings file to {}", bad.display()),
+                            Err(err) => {
+                                // Saving would overwrite the file with the defaults.
+                                error!("Not saving settings, moving the file failed: {}", err);
+                                settings_path = None;
+                            }
+                        }
+                        Settings::default()
+                    }
+                }
+            }
+            None => Settings::default(),
+        };
+
+        let ble_manager = BleManager::new(settings.ble.clone()).await?;
+        ble_manager
+            .set_known_devices(settings.known_devices.clone())
+            .await;
+        let port_manager = PortManager::new(settings.port.clone()).await;
+
+        let mut inspectors = FrameInspectors::default();
+        inspectors.set_config(settings.counter.clone());
+        let mut reassemblers = Reassemblers::default();
+        reassemblers.set_framing(settings.framing.clone());
+        reassemblers.set_overrides(known_framing(&settings));
+        reassemblers.set_profiles(settings.ble.registry().list());
+        let mut crc_checkers = CrcCheckers::default();
+        crc_checkers.set_config(settings.crc.clone());
+        crc_checkers.set_policies(known_crc_policies(&settings));
+
+        Ok(Arc::new(Self {
+            bus: EventBus::new(),
             ble_manager,
-            port_manager,
-            other_port: None,
+            port_manager: Mutex::new(port_manager),
+            other_port: Default::default(),
+            inspectors: Arc::new(Mutex::new(inspectors)),
+            decoded_port: Default::default(),
+            bridged_pair: Default::default(),
+            reassemblers: Arc::new(Mutex::new(reassemblers)),
+            crc_checkers: Arc::new(Mutex::new(crc_checkers)),
+            sinks: Default::default(),
+            bridge_counters: Default::default(),
+            missed_events: Default::default(),
+            settings: Mutex::new(settings),
+            settings_path,
+            updating: Default::default(),
         }))
     }
 
-    pub async fn set_resource_path(&mut self, resource_path: PathBuf) {
-        self.port_manager.set_resource_path(resource_path).await
+    /// Validates and applies `settings`, then writes them to the settings file.
+    ///
+    /// Port settings only affect pairs opened afterwards.
+    pub async fn update_settings(&self, settings: Settings) -> Result<(), Vec<FieldError>> {
+        let _updating = self.updating.lock().await;
+        self.apply_settings(settings).await
+    }
+
+    /// Edits the current settings with `edit` and applies them like
+    /// `update_settings`, without another update slipping in between.
+    ///
+    /// Nothing is applied or saved when `edit` fails or changes nothing.
+    pub async fn update_settings_with<T, F>(&self, edit: F) -> Result<T, Vec<FieldError>>
+    where
+        F: FnOnce(&mut Settings) -> Result<T, Vec<FieldError>>,
+    {
+        let _updating = self.updating.lock().await;
+        let mut settings = self.settings().await;
+        let before = settings.clone();
+        let value = edit(&mut settings)?;
+        if settings != before {
+            self.apply_settings(settings).await?;
+        }
+        Ok(value)
     }
 
-    pub async fn start_loop(&mut self, ui_tx: Sender<BleEvent>) {
-        let (device_tx, mut device_rx) = mpsc::channel(100);
-        self.ble_manager
-            .init_event_loop(device_tx)
+    /// Expects `updating` to be held by the caller.
+    async fn apply_settings(&self, settings: Settings) -> Result<(), Vec<FieldError>> {
+        settings.validate()?;
+        let old = std::mem::replace(&mut *self.settings.lock().await, settings.clone());
+
+        if settings.ble != old.ble {
+            self.ble_manager.set_settings(settings.ble.clone()).await;
+            self.reassemblers
+                .lock()
+                .await
+                .set_profiles(settings.ble.registry().list());
+        }
+        if settings.port != old.port {
+            self.port_manager
+                .lock()
+                .await
+                .set_settings(settings.port.clone());
+        }
+        if let Some(resource_path) = &settings.resource_path {
+            self.port_manager
+                .lock()
+                .await
+                .set_resource_path(resource_path.clone())
+                .await;
+        }
+        if settings.counter != old.counter {
+            self.inspectors
+                .lock()
+                .await
+                .set_config(settings.counter.clone());
+        }
+        if settings.framing != old.framing {
+            self.reassemblers
+                .lock()
+                .await
+                .set_framing(settings.framing.clone());
+        }
+        if settings.crc != old.crc {
+            self.crc_checkers
+                .lock()
+                .await
+                .set_config(settings.crc.clone());
+        }
+        if settings.known_devices != old.known_devices {
+            self.ble_manager
+                .set_known_devices(settings.known_devices.clone())
+                .await;
+            self.reassemblers
+                .lock()
+                .await
+                .set_overrides(known_framing(&settings));
+            self.crc_checkers
+                .lock()
+                .await
+                .set_policies(known_crc_policies(&settings));
+        }
+
+        let mut errors = Vec::new();
+        if settings.decoder != old.decoder {
+            let result = self.set_decoder(settings.decoder.clone()).await;
+            if let Err(err) = result.map_err(|e| e.to_string()) {
+                self.settings.lock().await.decoder = None;
+                errors.push(FieldError::new("decoder", err));
+            }
+        }
+
+        if let Some(path) = &self.settings_path {
+            if let Err(err) = self.settings().await.save(path) {
+                errors.push(FieldError::new("file", err.to_string()));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Opens the output described by `config` and starts fanning data out to
+    /// it, replacing any sink already registered under `name`.
+    pub async fn add_sink(
+        &self,
+        name: String,
+        device: Option<String>,
+        config: SinkConfig,
+    ) -> Result<SinkInfo, Box<dyn Error>> {
+        self.open_sink(name, device, None, config).await
+    }
+
+    async fn open_sink(
+        &self,
+        name: String,
+        device: Option<String>,
+        channel: Option<String>,
+        config: SinkConfig,
+    ) -> Result<SinkInfo, Box<dyn Error>> {
+        self.remove_sink(&name).await;
+
+        let (handle, location) = match &config {
+            SinkConfig::Port { index } => {
+                let (port, other_port) = self.port_manager.lock().await.init_pair(*index).await?;
+                (spawn_sink(Box::new(RawSink(Box::new(port)))), other_port)
+            }
+            SinkConfig::Serial(name) => {
+                let port = self.port_manager.lock().await.open_port(name)?;
+                (spawn_sink(Box::new(RawSink(Box::new(port)))), name.clone())
+            }
+            SinkConfig::File(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                (
+                    spawn_sink(Box::new(RawSink(Box::new(file)))),
+                    path.display().to_string(),
+                )
+            }
+            SinkConfig::Tcp(address) => spawn_tcp_sink(address).await?,
+            SinkConfig::Decoded(decoder) => {
+                let (writer, location): (Box<dyn Write + Send>, String) = match &decoder.output {
+                    DecodedOutput::Port => {
+                        let pair = self.settings.lock().await.port.decoded_pair;
+                        let (port, other_port) =
+                            self.port_manager.lock().await.init_pair(pair).await?;
+                        (Box::new(port), other_port)
+                    }
+                    DecodedOutput::File(path) => (
+                        Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+                        path.display().to_string(),
+                    ),
+                };
+                let decoder = FrameDecoder::new(decoder.clone(), writer)?;
+                (spawn_sink(Box::new(decoder)), location)
+            }
+        };
+
+        let info =
+            self.sinks
+                .lock()
+                .await
+                .insert(name, device, channel, config, Some(location), handle);
+        Ok(info)
+    }
+
+    pub async fn remove_sink(&self, name: &str) -> bool {
+        let handle = self.sinks.lock().await.remove(name);
+        match handle {
+            Some(handle) => {
+                handle.close().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Decodes frames into the "decoded" sink, or stops decoding when `None`.
+    pub async fn set_decoder(&self, config: Option<DecoderConfig>) -> Result<(), Box<dyn Error>> {
+        *self.decoded_port.lock().await = None;
+        match config {
+            Some(config) => {
+                let info = self
+                    .add_sink(DECODED_SINK.to_string(), None, SinkConfig::Decoded(config))
+                    .await?;
+                *self.decoded_port.lock().await = info.location;
+            }
+            None => {
+                self.remove_sink(DECODED_SINK).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the BLE side, closes every sink so queued data reaches its port
+    /// or file, then removes the port pairs when `port.remove_on_exit` is set.
+    pub async fn shutdown(&self) {
+        debug!("Shutting down");
+        self.ble_manager.shutdown().await;
+
+        let sinks = self.sinks.lock().await.list();
+        for sink in &sinks {
+            self.remove_sink(&sink.name).await;
+        }
+        *self.other_port.lock().await = None;
+        *self.bridged_pair.lock().await = None;
+        *self.decoded_port.lock().await = None;
+
+        let port_settings = self.settings.lock().await.port.clone();
+        if !port_settings.remove_on_exit {
+            return;
+        }
+        let pairs = sinks.iter().filter_map(|sink| match &sink.config {
+            SinkConfig::Port { index } => Some(*index),
+            SinkConfig::Decoded(DecoderConfig {
+                output: DecodedOutput::Port,
+                ..
+            }) => Some(port_settings.decoded_pair),
+            _ => None,
+        });
+        for index in pairs {
+            let output = self.port_manager.lock().await.remove_ports(index).await;
+            if !output.status.success() {
+                error!(
+                    "Error removing port pair {}: {}",
+                    index,
+                    String::from_utf8_lossy(&output.stdout)
+                );
+            }
+        }
+    }
+
+    pub async fn settings(&self) -> Settings {
+        self.settings.lock().await.clone()
+    }
+
+    pub async fn set_resource_path(&self, resource_path: PathBuf) {
+        let resource_path = self
+            .settings
+            .lock()
+            .await
+            .resource_path
+            .clone()
+            .unwrap_or(resource_path);
+        self.port_manager
+            .lock()
             .await
-            .expect("Error init event loop");
+            .set_resource_path(resource_path)
+            .await
+    }
 
-        let (mut port, other_port) = self.port_manager.init().await;
-        self.other_port = Some(other_port);
+    /// Starts the bridge on the configured pair, then keeps the known devices
+    /// up to date and auto-connects the first one marked for it that shows up.
+    pub async fn start_loop(self: &Arc<Self>) {
+        let pair = self.settings.lock().await.port.pair;
+        self.start_bridge(SinkConfig::Port { index: pair })
+            .await
+            .expect("Failed to start bridge");
+        tokio::spawn(self.clone().run_known_devices());
+    }
 
-        tokio::spawn(async move {
-            while let Some(event) = device_rx.recv().await {
-                match event {
-                    crate::ble::BleEvent::DeviceNotification(data) => {
-                        if let Err(err) = port.write(&data) {
-                            error!("Error sending Data to Port: {}", err);
+    async fn run_known_devices(self: Arc<Self>) {
+        let mut events = self.bus.subscribe(&[Topic::Lifecycle]);
+        let mut auto_connecting = self
+            .settings
+            .lock()
+            .await
+            .known_devices
+            .iter()
+            .any(|known| known.auto_connect);
+        if auto_connecting {
+            if let Err(err) = self.ble_manager.start_scan().await {
+                error!("Error scanning for known devices: {}", err);
+                auto_connecting = false;
+            }
+        }
+
+        while let Some(received) = events.recv().await {
+            let event = match received {
+                Received::Event(event) => event,
+                Received::Lagged { topic, missed } => {
+                    error!("Known devices missed {} {:?} events", missed, topic);
+                    continue;
+                }
+            };
+            match &*event {
+                BleEvent::DeviceDiscovered(device) | BleEvent::DeviceUpdated(device)
+                    if auto_connecting =>
+                {
+                    let known = self.settings.lock().await.known_device(&device.id).cloned();
+                    if let Some(known) = known.filter(|known| known.auto_connect) {
+                        auto_connecting = false;
+                        if let Err(err) = self.auto_connect(&device.id, &known).await {
+                            error!("Error auto-connecting {}: {}", device.id, err);
                         }
                     }
+                }
+                BleEvent::DeviceConnected(device) => {
+                    self.remember_device(device).await;
+                    if let Err(err) = self.open_device_port(&device.id).await {
+                        error!("Error opening the port of {}: {}", device.id, err);
+                    }
+                    if let Err(err) = self.open_device_decoder(&device.id).await {
+                        error!("Error opening the decoder of {}: {}", device.id, err);
+                    }
+                    self.open_channel_sinks(&device.id).await;
+                }
+                BleEvent::DeviceDisconnected(id) => {
+                    self.remove_sink(&device_port_sink(id)).await;
+                    self.remove_sink(&device_decoder_sink(id)).await;
+                }
+                _ => {}
+            }
+        }
+    }
 
-                    _ => {
-                        if let Err(err) = ui_tx.send(event).await {
-                            error!("Error sending event to UI: {}", err);
-                        }
+    /// Connects a known device, bridging it to the pair it used last time.
+    async fn auto_connect(&self, id: &str, known: &KnownDevice) -> Result<(), Box<dyn Error>> {
+        debug!("Auto-connecting {}", id);
+        self.ble_manager.stop_scan().await?;
+        if let Some(pair) = known.pair {
+            if *self.bridged_pair.lock().await != Some(pair) {
+                self.open_bridge_port(SinkConfig::Port { index: pair })
+                    .await?;
+            }
+        }
+        self.ble_manager.connect_device(id.to_string()).await
+    }
+
+    /// Adds `device` to the known devices or refreshes its port.
+    ///
+    /// The profile is left alone: it overrides detection, so only users set it.
+    async fn remember_device(&self, device: &Device) {
+        let pair = *self.bridged_pair.lock().await;
+        let port = self.other_port.lock().await.clone();
+
+        let result = self
+            .update_settings_with(|settings| {
+                let known = match settings.known_device_mut(&device.id) {
+                    Some(known) => known,
+                    None => {
+                        settings.known_devices.push(KnownDevice {
+                            address: device.id.clone(),
+                            alias: None,
+                            color: None,
+                            notes: None,
+                            pair: None,
+                            port: None,
+                            profile: None,
+                            port_name: None,
+                            framing: None,
+                            crc_policy: None,
+                            decoder: None,
+                            auto_connect: false,
+                        });
+                        settings.known_devices.last_mut().unwrap()
                     }
+                };
+                known.pair = pair.or(known.pair);
+                known.port = port.or(known.port.take());
+                Ok(())
+            })
+            .await;
+        if let Err(errors) = result {
+            for err in errors {
+                error!("Error remembering {}: {}", device.id, err);
+            }
+        }
+    }
+
+    /// Opens the device's own port when it has one set, closes it otherwise.
+    async fn open_device_port(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        let name = device_port_sink(id);
+        let port_name = self
+            .settings
+            .lock()
+            .await
+            .known_device(id)
+            .and_then(|known| known.port_name.clone());
+        match port_name {
+            Some(port_name) => {
+                self.add_sink(name, Some(id.to_string()), SinkConfig::Serial(port_name))
+                    .await?;
+            }
+            None => {
+                self.remove_sink(&name).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the device's own decoder when it has one set, closes it otherwise.
+    async fn open_device_decoder(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        let name = device_decoder_sink(id);
+        let decoder = self
+            .settings
+            .lock()
+            .await
+            .known_device(id)
+            .and_then(|known| known.decoder.clone());
+        match decoder {
+            Some(decoder) => {
+                self.add_sink(name, Some(id.to_string()), SinkConfig::Decoded(decoder))
+                    .await?;
+            }
+            None => {
+                self.remove_sink(&name).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the output of every separate channel of the device.
+    async fn open_channel_sinks(&self, id: &str) {
+        let ble = self.settings.lock().await.ble.clone();
+        if ble.channel_mode != ChannelMode::Separate {
+            return;
+        }
+        for channel in ble.channels {
+            let output = match channel.output {
+                Some(output) => output,
+                None => continue,
+            };
+            let name = channel_sink(id, &channel.name);
+            if let Err(err) = self
+                .open_sink(
+                    name,
+                    Some(id.to_string()),
+                    Some(channel.name.clone()),
+                    output,
+                )
+                .await
+            {
+                error!(
+                    "Error opening the {} channel of {}: {}",
+                    channel.name, id, err
+                );
+            }
+        }
+    }
+
+    pub async fn known_devices(&self) -> Vec<KnownDevice> {
+        self.settings.lock().await.known_devices.clone()
+    }
+
+    /// Adds or replaces the entry of `known.address`.
+    pub async fn set_known_device(&self, known: KnownDevice) -> Result<(), Vec<FieldError>> {
+        self.update_settings_with(|settings| {
+            match settings.known_device_mut(&known.address) {
+                Some(entry) => *entry = known,
+                None => settings.known_devices.push(known),
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Sets whether a known device is connected automatically on launch.
+    pub async fn set_auto_connect(
+        &self,
+        address: &str,
+        enabled: bool,
+    ) -> Result<(), Vec<FieldError>> {
+        self.update_settings_with(|settings| match settings.known_device_mut(address) {
+            Some(known) => {
+                known.auto_connect = enabled;
+                Ok(())
+            }
+            None => Err(vec![FieldError::new("address", "unknown device")]),
+        })
+        .await
+    }
+
+    /// Sets what happens to the device's frames that fail their checksum;
+    /// `None` uses the policy of the CRC settings.
+    pub async fn set_crc_policy(
+        &self,
+        address: &str,
+        policy: Option<BadFramePolicy>,
+    ) -> Result<(), Vec<FieldError>> {
+        self.update_settings_with(|settings| match settings.known_device_mut(address) {
+            Some(known) => {
+                known.crc_policy = policy;
+                Ok(())
+            }
+            None => Err(vec![FieldError::new("address", "unknown device")]),
+        })
+        .await
+    }
+
+    /// Drops a device from the known devices; false when it wasn't known.
+    pub async fn forget_device(&self, address: &str) -> Result<bool, Vec<FieldError>> {
+        self.update_settings_with(|settings| {
+            let count = settings.known_devices.len();
+            settings
+                .known_devices
+                .retain(|known| !known.address.eq_ignore_ascii_case(address));
+            Ok(settings.known_devices.len() != count)
+        })
+        .await
+    }
+
+    /// Opens the raw output every device's data goes to.
+    async fn open_bridge_port(&self, port: SinkConfig) -> Result<(), Box<dyn Error>> {
+        let pair = match &port {
+            SinkConfig::Port { index } => Some(*index),
+            _ => None,
+        };
+        let info = self.add_sink(PORT_SINK.to_string(), None, port).await?;
+        *self.other_port.lock().await = info.location;
+        *self.bridged_pair.lock().await = pair;
+        Ok(())
+    }
+
+    /// Starts the BLE event loop and forwards every device's data to `port`.
+    pub async fn start_bridge(&self, port: SinkConfig) -> Result<(), Box<dyn Error>> {
+        self.ble_manager.init_event_loop(self.bus.clone()).await?;
+
+        self.open_bridge_port(port).await?;
+
+        let decoder = self.settings.lock().await.decoder.clone();
+        if let Err(err) = self.set_decoder(decoder).await {
+            error!("Error opening decoded output: {}", err);
+        }
+
+        let bridge = Bridge {
+            reassemblers: self.reassemblers.clone(),
+            crc_checkers: self.crc_checkers.clone(),
+            inspectors: self.inspectors.clone(),
+            sinks: self.sinks.clone(),
+            counters: self.bridge_counters.clone(),
+            bus: self.bus.clone(),
+        };
+        let missed_events = self.missed_events.clone();
+        let mut events = self.bus.subscribe(&[Topic::Lifecycle, Topic::Data]);
+
+        tokio::spawn(async move {
+            while let Some(received) = events.recv().await {
+                let event = match received {
+                    Received::Event(event) => event,
+                    Received::Lagged { topic, missed } => {
+                        error!("Bridge missed {} {:?} events", missed, topic);
+                        missed_events.fetch_add(missed, Ordering::Relaxed);
+                        continue;
+                    }
+                };
+
+                match &*event {
+                    BleEvent::DeviceNotification(id, data) => bridge.push(id, None, data).await,
+
+                    BleEvent::MultiplexedNotification(data) => {
+                        bridge.push(&data.id, Some(data.tag), &data.data).await
+                    }
+
+                    BleEvent::ChannelNotification(data) => {
+                        bridge.sinks.lock().await.send(SinkData::Channel {
+                            id: data.id.clone(),
+                            channel: data.channel.clone(),
+                            bytes: data.data.clone(),
+                        });
+                    }
+
+                    BleEvent::DeviceConnected(device) => {
+                        let mut reassemblers = bridge.reassemblers.lock().await;
+                        reassemblers.set_device_profile(&device.id, device.profile.clone());
+                        reassemblers.reset(&device.id);
+                        drop(reassemblers);
+                        bridge.crc_checkers.lock().await.reset(&device.id);
+                        bridge.inspectors.lock().await.reset(&device.id);
+                    }
+
+                    _ => {}
                 }
             }
         });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::framing::LengthPrefix;
+    use crate::inspector::{CounterConfig, Endianness};
+
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+
+    /// Collects what a sink writes.
+    struct Capture(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A bridge with one sink collecting everything it's handed.
+    async fn capture(
+        reassemblers: Reassemblers,
+        inspectors: FrameInspectors,
+    ) -> (Bridge, Arc<std::sync::Mutex<Vec<u8>>>) {
+        let bridge = Bridge {
+            reassemblers: Arc::new(Mutex::new(reassemblers)),
+            crc_checkers: Default::default(),
+            inspectors: Arc::new(Mutex::new(inspectors)),
+            sinks: Default::default(),
+            counters: Default::default(),
+            bus: EventBus::new(),
+        };
+        let output = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handle = spawn_sink(Box::new(RawSink(Box::new(Capture(output.clone())))));
+        bridge.sinks.lock().await.insert(
+            "capture".to_string(),
+            None,
+            None,
+            SinkConfig::File("capture".into()),
+            None,
+            handle,
+        );
+        (bridge, output)
+    }
+
+    /// Waits for the capture sink to write out what it was handed.
+    async fn close(bridge: &Bridge) {
+        let handle = bridge.sinks.lock().await.remove("capture").unwrap();
+        handle.close().await;
+    }
+
+    #[tokio::test]
+    async fn multiplexed_characteristics_are_framed_separately() {
+        let mut reassemblers = Reassemblers::default();
+        reassemblers.set_framing(Framing::LengthPrefixed(LengthPrefix {
+            offset: 0,
+            width: 1,
+            endianness: Endianness::Little,
+            includes_header: false,
+        }));
+        let mut inspectors = FrameInspectors::default();
+        inspectors.set_config(Some(CounterConfig {
+            offset: 1,
+            width: 1,
+            endianness: Endianness::Little,
+            gap_marker: Some(vec![0xEE]),
+        }));
+        let (bridge, output) = capture(reassemblers, inspectors).await;
+
+        // Frames of both characteristics split across notifications, with
+        // the other characteristic's notifications in between.
+        bridge.push(DEVICE, Some(PRIMARY_TAG), &[2, 1]).await;
+        bridge.push(DEVICE, Some(5), &[3, 7, 0xB1]).await;
+        bridge.push(DEVICE, Some(PRIMARY_TAG), &[0xA1, 2]).await;
+        bridge.push(DEVICE, Some(5), &[0xB2, 3, 8]).await;
+        bridge.push(DEVICE, Some(PRIMARY_TAG), &[2, 0xA2]).await;
+        bridge.push(DEVICE, Some(5), &[0xB3, 0xB4]).await;
+
+        close(&bridge).await;
+
+        let expected = [
+            channel::tagged(PRIMARY_TAG, &[2, 1, 0xA1]),
+            channel::tagged(5, &[3, 7, 0xB1, 0xB2]),
+            channel::tagged(PRIMARY_TAG, &[2, 2, 0xA2]),
+            channel::tagged(5, &[3, 8, 0xB3, 0xB4]),
+        ]
+        .concat();
+        assert_eq!(*output.lock().unwrap(), expected);
+
+        // Each characteristic's counter runs on its own, so nothing is missing.
+        let stats = bridge.inspectors.lock().await.stats();
+        assert_eq!(stats[DEVICE].received, 4);
+        assert_eq!(stats[DEVICE].missing, 0);
+        assert_eq!(stats[DEVICE].out_of_order, 0);
+
+        let counters = bridge.counters.lock().await;
+        assert_eq!(counters[DEVICE].frames, 4);
+        assert_eq!(counters[DEVICE].bytes, expected.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn gap_marker_goes_in_front_of_the_frame_after_a_gap() {
+        let mut inspectors = FrameInspectors::default();
+        inspectors.set_config(Some(CounterConfig {
+            offset: 0,
+            width: 1,
+            endianness: Endianness::Little,
+            gap_marker: Some(vec![0xEE]),
+        }));
+        let (bridge, output) = capture(Reassemblers::default(), inspectors).await;
+        let mut events = bridge.bus.subscribe(&[Topic::Errors]);
+
+        bridge.push(DEVICE, None, &[1, 0xA1]).await;
+        bridge.push(DEVICE, None, &[4, 0xA4]).await;
+        close(&bridge).await;
+
+        assert_eq!(*output.lock().unwrap(), vec![1, 0xA1, 0xEE, 4, 0xA4]);
+        match events.recv().await {
+            Some(Received::Event(event)) => match &*event {
+                BleEvent::FrameLoss(loss) => assert_eq!(loss.missing, 2),
+                event => panic!("unexpected event {:?}", event),
+            },
+            _ => panic!("no frame loss"),
+        }
     }
 }
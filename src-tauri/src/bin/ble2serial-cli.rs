@@ -0,0 +1,371 @@
+//! Runs the bridge without the Tauri window, for headless machines and scripts.
+//!
+//! Exit codes: 0 success, 1 other failure, 2 usage error, 3 no Bluetooth
+//! adapter, 4 device not found in time, 5 connection or port failure.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use ble2serial::app::{AppState, AppStateType};
+use ble2serial::ble::BleEvent;
+use ble2serial::bus::{Received, Subscription, Topic};
+use ble2serial::settings;
+use ble2serial::sink::SinkConfig;
+use ble2serial::{rest, rpc};
+use clap::{Parser, Subcommand};
+
+const EXIT_FAILURE: u8 = 1;
+const EXIT_NO_ADAPTER: u8 = 3;
+const EXIT_NOT_FOUND: u8 = 4;
+const EXIT_CONNECTION: u8 = 5;
+
+#[derive(Parser)]
+#[command(name = "ble2serial-cli", about = "BLE to serial bridge without the UI")]
+struct Cli {
+    /// Print JSON, one value per line, instead of text.
+    #[arg(long, global = true)]
+    json: bool,
+    /// Settings file to use instead of the app's.
+    #[arg(long, global = true)]
+    settings: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List devices matching the name filter.
+    Scan {
+        /// Seconds to scan for.
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+    },
+    /// Connect to a device and print its notifications until Ctrl-C.
+    Connect {
+        id: String,
+        /// Seconds to look for the device.
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// Forward a device's data to a serial port until Ctrl-C, serving the
+    /// control socket and HTTP API when enabled in the settings.
+    Bridge {
+        #[arg(long)]
+        id: String,
+        /// Serial port to write to; the configured com0com pair when omitted.
+        #[arg(long)]
+        port: Option<String>,
+        /// Seconds to look for the device.
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// Print the services and characteristics of a device.
+    Gatt {
+        id: String,
+        /// Seconds to look for the device.
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+}
+
+struct Failure {
+    code: u8,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: u8, message: impl fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Waits up to `timeout` for an event `matches` picks out.
+async fn wait_for<T>(
+    events: &mut Subscription,
+    timeout: Duration,
+    mut matches: impl FnMut(&BleEvent) -> Option<T>,
+) -> Option<T> {
+    let wait = async {
+        while let Some(received) = events.recv().await {
+            if let Received::Event(event) = received {
+                if let Some(value) = matches(&event) {
+                    return Some(value);
+                }
+            }
+        }
+        None
+    };
+    tokio::time::timeout(timeout, wait).await.ok().flatten()
+}
+
+async fn start_events(state: &AppStateType) -> Result<(), Failure> {
+    state
+        .ble_manager
+        .init_event_loop(state.bus.clone())
+        .await
+        .map_err(|e| Failure::new(EXIT_FAILURE, e))
+}
+
+/// Connects to the device at address `id`, scanning for it when the OS doesn't know it.
+async fn connect_device(state: &AppStateType, id: &str, timeout: Duration) -> Result<(), Failure> {
+    let mut events = state.bus.subscribe(&[Topic::Lifecycle, Topic::Errors]);
+
+    let id = state
+        .ble_manager
+        .locate(id, timeout)
+        .await
+        .map_err(|e| Failure::new(EXIT_FAILURE, e))?
+        .ok_or_else(|| Failure::new(EXIT_NOT_FOUND, format!("Device {} not found", id)))?;
+    let id = id.as_str();
+
+    state
+        .ble_manager
+        .connect_device(id.to_string())
+        .await
+        .map_err(|e| Failure::new(EXIT_CONNECTION, e))?;
+    let connected = wait_for(&mut events, timeout, |event| match event {
+        BleEvent::DeviceConnected(device) if device.id == id => Some(Ok(())),
+        BleEvent::DeviceError(err) => Some(Err(err.clone())),
+        _ => None,
+    })
+    .await;
+    match connected {
+        Some(Ok(())) => Ok(()),
+        Some(Err(err)) => Err(Failure::new(EXIT_CONNECTION, err)),
+        None => Err(Failure::new(
+            EXIT_CONNECTION,
+            format!("Timed out connecting to {}", id),
+        )),
+    }
+}
+
+/// Prints events until Ctrl-C, or fails when the device disconnects.
+async fn run_until_stopped(id: &str, mut events: Subscription, json: bool) -> Result<(), Failure> {
+    loop {
+        let received = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            received = events.recv() => match received {
+                Some(received) => received,
+                None => break,
+            },
+        };
+        let event = match received {
+            Received::Event(event) => event,
+            Received::Lagged { topic, missed } => {
+                eprintln!("Missed {} {:?} events", missed, topic);
+                continue;
+            }
+        };
+
+        match &*event {
+            BleEvent::DeviceNotification(device, data) if device == id => {
+                if json {
+                    println!("{}", serde_json::json!({ "id": device, "data": hex(data) }));
+                } else {
+                    println!("{}: {}", device, hex(data));
+                }
+            }
+            BleEvent::MultiplexedNotification(data) if data.id == id => {
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::json!({ "id": data.id, "tag": data.tag, "data": hex(&data.data) })
+                    );
+                } else {
+                    println!("{} #{}: {}", data.id, data.tag, hex(&data.data));
+                }
+            }
+            BleEvent::DeviceDisconnected(device) if device == id => {
+                return Err(Failure::new(EXIT_CONNECTION, "Device disconnected"));
+            }
+            BleEvent::DeviceError(err) => eprintln!("Device error: {}", err),
+            BleEvent::FrameLoss(loss) if json => {
+                println!("{}", serde_json::json!({ "frame_loss": loss }));
+            }
+            BleEvent::FrameLoss(loss) => {
+                eprintln!("{}: {} frames missing", loss.id, loss.missing);
+            }
+            BleEvent::CrcError(crc_error) if json => {
+                println!("{}", serde_json::json!({ "crc_error": crc_error }));
+            }
+            BleEvent::CrcError(crc_error) => eprintln!("{}: bad CRC", crc_error.id),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn scan(state: &AppStateType, timeout: Duration, json: bool) -> Result<(), Failure> {
+    start_events(state).await?;
+    state
+        .ble_manager
+        .start_scan()
+        .await
+        .map_err(|e| Failure::new(EXIT_FAILURE, e))?;
+    tokio::time::sleep(timeout).await;
+
+    state
+        .ble_manager
+        .stop_scan()
+        .await
+        .map_err(|e| Failure::new(EXIT_FAILURE, e))?;
+    let devices = state
+        .ble_manager
+        .get_devices()
+        .await
+        .map_err(|e| Failure::new(EXIT_FAILURE, e))?;
+
+    if json {
+        println!("{}", serde_json::json!(devices));
+    } else {
+        for device in devices {
+            let rssi = device.rssi.map(|rssi| rssi.to_string()).unwrap_or_default();
+            println!("{}\t{}\t{}", device.id, rssi, device.name);
+        }
+    }
+    Ok(())
+}
+
+async fn connect(
+    state: &AppStateType,
+    id: &str,
+    timeout: Duration,
+    json: bool,
+) -> Result<(), Failure> {
+    start_events(state).await?;
+    let events = state
+        .bus
+        .subscribe(&[Topic::Lifecycle, Topic::Data, Topic::Errors]);
+    connect_device(state, id, timeout).await?;
+    run_until_stopped(id, events, json).await
+}
+
+async fn bridge(
+    state: &AppStateType,
+    id: &str,
+    port: Option<String>,
+    timeout: Duration,
+    json: bool,
+) -> Result<(), Failure> {
+    let settings = state.settings().await;
+    let config = match port {
+        Some(name) => SinkConfig::Serial(name),
+        None => SinkConfig::Port {
+            index: settings.port.pair,
+        },
+    };
+    state
+        .start_bridge(config)
+        .await
+        .map_err(|e| Failure::new(EXIT_CONNECTION, e))?;
+    rpc::spawn_server(state.clone(), &settings.rpc);
+    rest::spawn_server(state.clone(), &settings.http);
+    let location = state.other_port.lock().await.clone().unwrap_or_default();
+    if json {
+        println!("{}", serde_json::json!({ "port": location }));
+    } else {
+        println!("Bridging to {}", location);
+    }
+
+    let events = state.bus.subscribe(&[Topic::Lifecycle, Topic::Errors]);
+    connect_device(state, id, timeout).await?;
+    run_until_stopped(id, events, json).await
+}
+
+async fn gatt(
+    state: &AppStateType,
+    id: &str,
+    timeout: Duration,
+    json: bool,
+) -> Result<(), Failure> {
+    state.ble_manager.set_auto_subscribe(false);
+    start_events(state).await?;
+    connect_device(state, id, timeout).await?;
+
+    let services = state
+        .ble_manager
+        .gatt(id)
+        .await
+        .map_err(|e| Failure::new(EXIT_CONNECTION, e))?;
+
+    if json {
+        println!("{}", serde_json::json!(services));
+    } else {
+        for service in services {
+            let kind = if service.primary {
+                "primary"
+            } else {
+                "secondary"
+            };
+            println!("{} ({})", service.uuid, kind);
+            for characteristic in service.characteristics {
+                println!(
+                    "  {} [{}]",
+                    characteristic.uuid,
+                    characteristic.properties.join(", ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let settings_path = cli.settings.or_else(settings::default_path);
+    let state = match AppState::with_settings_path(settings_path).await {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(EXIT_NO_ADAPTER);
+        }
+    };
+
+    // Bundled com0com sits next to the executable unless the settings say otherwise.
+    if let Some(dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("com0com")))
+    {
+        state.set_resource_path(dir).await;
+    }
+
+    // Device ids are addresses, which btleplug formats in upper case.
+    let result = match cli.command {
+        Command::Scan { timeout } => scan(&state, Duration::from_secs(timeout), cli.json).await,
+        Command::Connect { id, timeout } => {
+            let id = id.to_uppercase();
+            connect(&state, &id, Duration::from_secs(timeout), cli.json).await
+        }
+        Command::Bridge { id, port, timeout } => {
+            let id = id.to_uppercase();
+            bridge(&state, &id, port, Duration::from_secs(timeout), cli.json).await
+        }
+        Command::Gatt { id, timeout } => {
+            let id = id.to_uppercase();
+            gatt(&state, &id, Duration::from_secs(timeout), cli.json).await
+        }
+    };
+
+    // Disconnects whatever the command connected and flushes the port.
+    state.shutdown().await;
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("{}", failure.message);
+            ExitCode::from(failure.code)
+        }
+    }
+}
@@ -1,21 +1,85 @@
-use btleplug::api::{Central, CentralEvent, Manager as _, Peripheral, ScanFilter};
-use btleplug::platform::{Adapter, Manager, PeripheralId};
-use futures::stream::StreamExt;
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, CharPropFlags, Manager as _, Peripheral, PeripheralProperties,
+    ScanFilter,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral as PlatformPeripheral, PeripheralId};
+use futures::future;
+use futures::stream::{self, StreamExt};
 use log::{debug, error};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
 use uuid::Uuid;
 
-const SERVICE_UUID: Uuid = Uuid::from_u128(0x0000ffa0_0000_1000_8000_00805f9b34fb);
-const CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x0000ffa1_0000_1000_8000_00805f9b34fb);
+use crate::advertisement::{Advertisement, AdvertisementConfig, AdvertisementData, Deduplicator};
+use crate::bus::EventBus;
+use crate::channel::{ChannelData, ChannelMode, TaggedData, PRIMARY_TAG};
+use crate::crc::CrcError;
+use crate::device_info::{BatteryLevel, BatteryMonitor, DeviceInfo, BATTERY_LEVEL};
+use crate::inspector::FrameLoss;
+use crate::poll;
+use crate::session::{SessionHandle, Sessions};
+use crate::settings::{BleSettings, KnownDevice};
+use crate::write_queue::{WriteCounters, WriteQueue, WriteStats};
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
 pub struct Device {
     pub id: String,
     pub name: String,
+    /// Signal strength of the latest advertisement in dBm.
+    #[serde(default)]
+    pub rssi: Option<i16>,
+    #[serde(default)]
+    pub tx_power: Option<i16>,
+    /// Milliseconds since the Unix epoch of the latest advertisement.
+    #[serde(default)]
+    pub last_seen: u64,
+    /// Service UUIDs the device advertises.
+    #[serde(default)]
+    pub services: Vec<Uuid>,
+    #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Profile the device was connected with.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Read from the device on connect.
+    #[serde(default)]
+    pub info: Option<DeviceInfo>,
+}
+
+impl Device {
+    fn from_properties(id: String, name: String, properties: PeripheralProperties) -> Self {
+        Self {
+            id,
+            name,
+            rssi: properties.rssi,
+            tx_power: properties.tx_power_level,
+            last_seen: now_millis(),
+            services: properties.services,
+            alias: None,
+            color: None,
+            notes: None,
+            profile: None,
+            info: None,
+        }
+    }
+
+    /// Copies what users set for the device, clearing it when unknown.
+    fn annotate(&mut self, known: Option<&KnownDevice>) {
+        self.alias = known.and_then(|known| known.alias.clone());
+        self.color = known.and_then(|known| known.color.clone());
+        self.notes = known.and_then(|known| known.notes.clone());
+    }
 }
 
 impl fmt::Display for Device {
@@ -26,147 +90,703 @@ impl fmt::Display for Device {
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum BleEvent {
-    DeviceDiscovered(Vec<Device>),
+    /// A device showed up in the scan for the first time.
+    DeviceDiscovered(Device),
     DeviceConnected(Device),
-    DeviceDisconnected,
+    /// Address of the device that disconnected.
+    DeviceDisconnected(String),
+    /// A known device advertised again, usually with a new RSSI.
     DeviceUpdated(Device),
-    DeviceNotification(Vec<u8>),
+    /// The device hasn't advertised within the device timeout.
+    DeviceLost(String),
+    /// Every advertisement of a listed device.
+    DeviceAdvertisement(AdvertisementData),
+    DeviceNotification(String, Vec<u8>),
+    /// Data of a separate channel.
+    ChannelNotification(ChannelData),
+    /// Data of any of the device's characteristics when multiplexing.
+    MultiplexedNotification(TaggedData),
+    BatteryLevel(BatteryLevel),
+    /// The battery level dropped below the low battery threshold.
+    BatteryLow(BatteryLevel),
     DeviceError(String),
+    FrameLoss(FrameLoss),
+    CrcError(CrcError),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct GattCharacteristic {
+    pub uuid: Uuid,
+    pub properties: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct GattService {
+    pub uuid: Uuid,
+    pub primary: bool,
+    pub characteristics: Vec<GattCharacteristic>,
+}
+
+/// How long connecting by address scans for a device the OS doesn't know yet.
+pub const ADDRESS_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often devices are checked against the device timeout.
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long each shutdown step may take before it is given up on.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+const PROPERTY_NAMES: [(CharPropFlags, &str); 8] = [
+    (CharPropFlags::BROADCAST, "broadcast"),
+    (CharPropFlags::READ, "read"),
+    (
+        CharPropFlags::WRITE_WITHOUT_RESPONSE,
+        "write_without_response",
+    ),
+    (CharPropFlags::WRITE, "write"),
+    (CharPropFlags::NOTIFY, "notify"),
+    (CharPropFlags::INDICATE, "indicate"),
+    (
+        CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+        "authenticated_signed_writes",
+    ),
+    (CharPropFlags::EXTENDED_PROPERTIES, "extended_properties"),
+];
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Publishes the payloads picked from an advertisement of `id` as data of the
+/// device, for devices that are never connected.
+async fn forward_advertisement(
+    central: &Adapter,
+    devices: &DeviceTable<PeripheralId>,
+    config: &AdvertisementConfig,
+    dedup: &mut Deduplicator,
+    bus: &EventBus,
+    id: &PeripheralId,
+    payloads: Vec<(u128, &[u8])>,
+) -> Result<(), Box<dyn Error>> {
+    if payloads.is_empty() {
+        return Ok(());
+    }
+    let peripheral = central.peripheral(id).await?;
+    let address = peripheral.address();
+    let device_id = address.to_string();
+    let known = devices.get(&device_id).await.is_some();
+    if !config.accepts(&device_id, known) {
+        return Ok(());
+    }
+
+    let rssi = peripheral.properties().await?.and_then(|p| p.rssi);
+    let timestamp = now_millis();
+    let window = Duration::from_millis(config.dedup_window_ms);
+    for (key, payload) in payloads {
+        if !dedup.check(&device_id, key, payload, window) {
+            continue;
+        }
+        let data = config.encode(address.into_inner(), rssi, timestamp, payload);
+        bus.publish(BleEvent::DeviceNotification(device_id.clone(), data));
+    }
+    Ok(())
+}
+
+/// Connection and notification counters of one device, kept across reconnects.
+#[derive(Default)]
+pub struct DeviceCounters {
+    pub connected: AtomicBool,
+    pub connections: AtomicU64,
+    pub notifications: AtomicU64,
+    pub notification_bytes: AtomicU64,
+    /// Failures while setting up the device after it connected.
+    pub errors: AtomicU64,
+    /// Polls that started later than their jitter tolerance.
+    pub late_polls: Arc<AtomicU64>,
+    pub writes: Arc<WriteCounters>,
+}
+
+impl DeviceCounters {
+    pub fn count_notification(&self, len: usize) {
+        self.notifications.fetch_add(1, Ordering::Relaxed);
+        self.notification_bytes
+            .fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+/// Discovered devices and the platform handle used to reach each of them.
+///
+/// The table is locked for lookups and updates only, never across a BLE
+/// operation, so a slow connect doesn't hold up listing devices.
+pub struct DeviceTable<P> {
+    devices: Mutex<HashMap<String, (P, Device)>>,
+}
+
+impl<P> Default for DeviceTable<P> {
+    fn default() -> Self {
+        Self {
+            devices: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<P: Clone + PartialEq> DeviceTable<P> {
+    /// Adds or refreshes a device; true when it wasn't known yet.
+    pub async fn insert(&self, handle: P, device: Device) -> bool {
+        self.devices
+            .lock()
+            .await
+            .insert(device.id.clone(), (handle, device))
+            .is_none()
+    }
+
+    /// Drops devices last seen before `cutoff`, except those in `keep`, and
+    /// returns their ids.
+    pub async fn remove_stale(&self, cutoff: u64, keep: &HashSet<String>) -> Vec<String> {
+        let mut devices = self.devices.lock().await;
+        let stale: Vec<String> = devices
+            .iter()
+            .filter(|(id, (_, device))| device.last_seen < cutoff && !keep.contains(*id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &stale {
+            devices.remove(id);
+        }
+        stale
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Device> {
+        self.devices
+            .lock()
+            .await
+            .get(id)
+            .map(|(_, device)| device.clone())
+    }
+
+    /// The id of the device reached through `handle`.
+    pub async fn id_of(&self, handle: &P) -> Option<String> {
+        self.devices
+            .lock()
+            .await
+            .iter()
+            .find(|(_, (h, _))| h == handle)
+            .map(|(id, _)| id.clone())
+    }
+
+    pub async fn handles(&self) -> Vec<(String, P)> {
+        self.devices
+            .lock()
+            .await
+            .iter()
+            .map(|(id, (handle, _))| (id.clone(), handle.clone()))
+            .collect()
+    }
+
+    /// Every device, strongest signal first.
+    pub async fn list(&self) -> Vec<Device> {
+        let mut devices: Vec<Device> = self
+            .devices
+            .lock()
+            .await
+            .values()
+            .map(|(_, device)| device.clone())
+            .collect();
+        devices.sort_by(|a, b| b.rssi.cmp(&a.rssi).then_with(|| a.name.cmp(&b.name)));
+        devices
+    }
+
+    /// Applies `update` to every device.
+    pub async fn update_all(&self, mut update: impl FnMut(&mut Device)) {
+        for (_, device) in self.devices.lock().await.values_mut() {
+            update(device);
+        }
+    }
+
+    /// Runs `operation` on the handle of device `id` after releasing the table.
+    pub async fn with_handle<F, Fut, T>(&self, id: &str, operation: F) -> Result<T, Box<dyn Error>>
+    where
+        F: FnOnce(P) -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error>>>,
+    {
+        let handle = self
+            .devices
+            .lock()
+            .await
+            .get(id)
+            .map(|(handle, _)| handle.clone())
+            .ok_or("Device not found")?;
+        operation(handle).await
+    }
+}
+
+/// Keeps the counters, sessions and listeners up to date as devices connect
+/// and disconnect.
+struct Connections<P> {
+    devices: Arc<DeviceTable<P>>,
+    counters: Arc<Mutex<HashMap<String, Arc<DeviceCounters>>>>,
+    sessions: Arc<Mutex<Sessions>>,
+    bus: EventBus,
+}
+
+impl<P: Clone + PartialEq> Connections<P> {
+    /// Counts a connection of `id`; the returned counters are kept across
+    /// reconnects.
+    async fn connecting(&self, id: &str) -> Arc<DeviceCounters> {
+        let counters = self
+            .counters
+            .lock()
+            .await
+            .entry(id.to_string())
+            .or_default()
+            .clone();
+        counters.connected.store(true, Ordering::Relaxed);
+        counters.connections.fetch_add(1, Ordering::Relaxed);
+        counters
+    }
+
+    /// Starts forwarding the device's data, replacing an earlier session.
+    async fn connected(&self, session: SessionHandle, device: Device) {
+        self.sessions.lock().await.open(session).await;
+        self.bus.publish(BleEvent::DeviceConnected(device));
+    }
+
+    /// Ends the session of the device behind `handle`; ignored for devices
+    /// that aren't listed.
+    async fn disconnected(&self, handle: &P) {
+        let address = match self.devices.id_of(handle).await {
+            Some(address) => address,
+            None => return,
+        };
+        if let Some(counters) = self.counters.lock().await.get(&address) {
+            counters.connected.store(false, Ordering::Relaxed);
+        }
+        self.sessions.lock().await.close(&address).await;
+        self.bus.publish(BleEvent::DeviceDisconnected(address));
+    }
 }
 
 pub struct BleManager {
     pub central: Adapter,
 
-    devices: Arc<Mutex<HashMap<String, (PeripheralId, Device)>>>,
+    devices: Arc<DeviceTable<PeripheralId>>,
+    settings: Arc<Mutex<BleSettings>>,
+    auto_subscribe: Arc<AtomicBool>,
+    /// Whether a scan started with [`Self::start_scan`] is running; the
+    /// adapter can't tell.
+    scanning: AtomicBool,
+    counters: Arc<Mutex<HashMap<String, Arc<DeviceCounters>>>>,
+    /// Event loop failures that couldn't be tied to a device.
+    event_loop_errors: Arc<AtomicU64>,
+    sessions: Arc<Mutex<Sessions>>,
+    /// Remembered devices by id.
+    known: Arc<Mutex<HashMap<String, KnownDevice>>>,
 }
 
 impl BleManager {
-    pub async fn new() -> Result<Self, Box<dyn Error>> {
+    pub async fn new(settings: BleSettings) -> Result<Self, Box<dyn Error>> {
         let central = Self::get_central().await?;
         let ble_manager = BleManager {
             central,
             devices: Default::default(),
+            settings: Arc::new(Mutex::new(settings)),
+            auto_subscribe: Arc::new(AtomicBool::new(true)),
+            scanning: AtomicBool::new(false),
+            counters: Default::default(),
+            event_loop_errors: Default::default(),
+            sessions: Default::default(),
+            known: Default::default(),
         };
         Ok(ble_manager)
     }
 
-    pub async fn init_event_loop(
-        &self,
-        device_tx: mpsc::Sender<BleEvent>,
-    ) -> Result<(), Box<dyn Error>> {
+    /// Whether connected devices are subscribed to the configured
+    /// characteristic. When off, connecting only makes the GATT table
+    /// available.
+    pub fn set_auto_subscribe(&self, enabled: bool) {
+        self.auto_subscribe.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Applies to devices discovered or connected from now on.
+    pub async fn set_settings(&self, settings: BleSettings) {
+        *self.settings.lock().await = settings;
+    }
+
+    /// Replaces the remembered devices, whose aliases and profiles apply from now on.
+    pub async fn set_known_devices(&self, known_devices: Vec<KnownDevice>) {
+        let known: HashMap<String, KnownDevice> = known_devices
+            .into_iter()
+            .map(|known| (known.address.to_uppercase(), known))
+            .collect();
+        self.devices
+            .update_all(|device| device.annotate(known.get(&device.id)))
+            .await;
+        *self.known.lock().await = known;
+    }
+
+    pub async fn init_event_loop(&self, bus: EventBus) -> Result<(), Box<dyn Error>> {
         let mut events = self.central.events().await?;
 
         let central_copy = self.central.clone();
 
         let devices_clone = self.devices.clone();
+        let settings = self.settings.clone();
+        let auto_subscribe = self.auto_subscribe.clone();
+        let event_loop_errors = self.event_loop_errors.clone();
+        let known = self.known.clone();
+        let connections = Connections {
+            devices: self.devices.clone(),
+            counters: self.counters.clone(),
+            sessions: self.sessions.clone(),
+            bus: bus.clone(),
+        };
+
+        let stale_devices = self.devices.clone();
+        let stale_settings = self.settings.clone();
+        let stale_counters = self.counters.clone();
+        let stale_bus = bus.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STALE_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let timeout = stale_settings.lock().await.device_timeout_secs;
+                let cutoff = now_millis().saturating_sub(timeout * 1000);
+                // Connected devices stop advertising but are still around.
+                let connected: HashSet<String> = stale_counters
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(_, counters)| counters.connected.load(Ordering::Relaxed))
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                for id in stale_devices.remove_stale(cutoff, &connected).await {
+                    debug!("Lost: {}", id);
+                    stale_bus.publish(BleEvent::DeviceLost(id));
+                }
+            }
+        });
 
         tokio::spawn(async move {
+            let mut dedup = Deduplicator::default();
             while let Some(event) = events.next().await {
                 match event {
-                    CentralEvent::DeviceDiscovered(id) => {
-                        let peripheral = central_copy.peripheral(&id).await.unwrap();
-                        debug!("Discovered: {:?}", peripheral);
-                        let property = peripheral.properties().await.unwrap().unwrap();
-                        let name = property.local_name;
-                        let id = peripheral.address().to_string();
-
-                        if let Some(name) = name {
-                            if !name.contains("BioSignal") {
+                    CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => {
+                        let peripheral = match central_copy.peripheral(&id).await {
+                            Ok(peripheral) => peripheral,
+                            Err(err) => {
+                                error!("Error getting discovered peripheral: {}", err);
+                                event_loop_errors.fetch_add(1, Ordering::Relaxed);
                                 continue;
                             }
-                            debug!("Discovered: {:?}", peripheral);
-                            let device = Device {
-                                id: id.clone(),
-                                name,
-                            };
-                            devices_clone
-                                .lock()
-                                .await
-                                .insert(id.clone(), (peripheral.id(), device));
-
-                            let devices = devices_clone.lock().await;
-                            let devices = devices
-                                .clone()
-                                .into_iter()
-                                .map(|(_, device)| Device {
-                                    id: device.1.id.clone(),
-                                    name: device.1.name.clone(),
-                                })
-                                .collect::<Vec<Device>>();
-
-                            if let Err(err) =
-                                device_tx.send(BleEvent::DeviceDiscovered(devices)).await
-                            {
-                                error!("Error sending device discovered: {:?}", err);
+                        };
+                        let property = match peripheral.properties().await {
+                            Ok(Some(property)) => property,
+                            Ok(None) => continue,
+                            Err(err) => {
+                                error!("Error reading peripheral properties: {}", err);
+                                event_loop_errors.fetch_add(1, Ordering::Relaxed);
+                                continue;
                             }
+                        };
+                        let name = match &property.local_name {
+                            Some(name) => name.clone(),
+                            None => continue,
+                        };
+                        if !name.contains(&settings.lock().await.name_filter) {
+                            continue;
                         }
+
+                        let address = peripheral.address().to_string();
+                        let advertisement =
+                            AdvertisementData::from_properties(address.clone(), &property);
+                        let mut device = Device::from_properties(address, name, property);
+                        device.annotate(known.lock().await.get(&device.id));
+                        if devices_clone.insert(peripheral.id(), device.clone()).await {
+                            debug!("Discovered: {}", device);
+                            bus.publish(BleEvent::DeviceDiscovered(device));
+                        } else {
+                            bus.publish(BleEvent::DeviceUpdated(device));
+                        }
+                        bus.publish(BleEvent::DeviceAdvertisement(advertisement));
                     }
-                    // CentralEvent::DeviceUpdated(id) => todo!(),
                     CentralEvent::DeviceConnected(id) => {
-                        let peripheral = central_copy.peripheral(&id).await.unwrap();
+                        let peripheral = match central_copy.peripheral(&id).await {
+                            Ok(peripheral) => peripheral,
+                            Err(err) => {
+                                error!("Error getting connected peripheral: {}", err);
+                                event_loop_errors.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                        };
                         debug!("Connected: {:?}", peripheral);
-                        let property = peripheral.properties().await.unwrap().unwrap();
                         let id = peripheral.address().to_string();
-                        let device = devices_clone
+                        let mut device = match devices_clone.get(&id).await {
+                            Some(device) => device,
+                            None => {
+                                error!("Connected to unknown device {}", id);
+                                event_loop_errors.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                        };
+
+                        let device_counters = connections.connecting(&id).await;
+
+                        if !auto_subscribe.load(Ordering::Relaxed) {
+                            connections.connected(SessionHandle::new(id), device).await;
+                            continue;
+                        }
+
+                        if let Err(err) = peripheral.discover_services().await {
+                            error!("Error discovering services: {}", err);
+                            device_counters.errors.fetch_add(1, Ordering::Relaxed);
+                            bus.publish(BleEvent::DeviceError(format!(
+                                "Failed to discover services: {}",
+                                err
+                            )));
+                            continue;
+                        }
+                        let characteristics = peripheral.characteristics();
+                        let custom = known
                             .lock()
                             .await
                             .get(&id)
-                            .expect("No device found")
-                            .1
-                            .clone();
-
-                        peripheral
-                            .discover_services()
-                            .await
-                            .expect("Failed to discover services");
-                        let characteristics = peripheral.characteristics();
+                            .and_then(|known| known.profile.clone());
+                        let registry = settings.lock().await.registry();
+                        // Only a profile the user set for the device skips
+                        // detection; a registry profile with the same UUIDs
+                        // keeps its write characteristic and framing.
+                        let profile = match custom {
+                            Some(custom) => Some(
+                                registry
+                                    .find(custom.service_uuid, custom.characteristic_uuid)
+                                    .cloned()
+                                    .unwrap_or_else(|| custom.into_profile()),
+                            ),
+                            None => registry.detect(&characteristics).cloned(),
+                        };
 
-                        let rx_characteristic = match characteristics.iter().find(|c| {
-                            c.service_uuid == SERVICE_UUID && c.uuid == CHARACTERISTIC_UUID
-                        }) {
-                            Some(c) => c,
+                        let found = profile.and_then(|profile| {
+                            let notify = profile.notify_characteristic(&characteristics)?;
+                            Some((notify.clone(), profile))
+                        });
+                        let (rx_characteristic, profile) = match found {
+                            Some(found) => found,
                             None => {
                                 error!("No characteristic found");
-                                peripheral.disconnect().await.unwrap();
-                                device_tx
-                                    .send(BleEvent::DeviceError(
-                                        "No characteristic found".to_string(),
-                                    ))
-                                    .await
-                                    .unwrap();
+                                device_counters.errors.fetch_add(1, Ordering::Relaxed);
+                                if let Err(err) = peripheral.disconnect().await {
+                                    error!("Error disconnecting: {}", err);
+                                }
+                                bus.publish(BleEvent::DeviceError(
+                                    "No characteristic found".to_string(),
+                                ));
                                 continue;
                             }
                         };
 
-                        peripheral
-                            .subscribe(rx_characteristic)
-                            .await
-                            .expect("Failed to subscribe");
-
-                        let mut notifications = peripheral.notifications().await.unwrap();
-                        let device_tx_clone = device_tx.clone();
-
-                        tokio::spawn(async move {
-                            while let Some(notification) = notifications.next().await {
-                                if let Err(err) = device_tx_clone
-                                    .send(BleEvent::DeviceNotification(notification.value))
-                                    .await
-                                {
-                                    error!("Error sending notification: {:?}", err);
-                                    break;
+                        debug!("Using the {} profile for {}", profile.name, id);
+                        let mut polls = Vec::new();
+                        let subscribed = match &profile.poll {
+                            Some(config) => {
+                                polls.push((rx_characteristic.clone(), config.clone()));
+                                Ok(())
+                            }
+                            None => peripheral.subscribe(&rx_characteristic).await,
+                        };
+                        let notifications = match subscribed {
+                            Ok(()) => peripheral.notifications().await,
+                            Err(err) => Err(err),
+                        };
+                        let notifications = match notifications {
+                            Ok(notifications) => notifications,
+                            Err(err) => {
+                                error!("Error subscribing: {}", err);
+                                device_counters.errors.fetch_add(1, Ordering::Relaxed);
+                                bus.publish(BleEvent::DeviceError(format!(
+                                    "Failed to subscribe: {}",
+                                    err
+                                )));
+                                continue;
+                            }
+                        };
+
+                        let info = DeviceInfo::read(&peripheral, &characteristics).await;
+                        let mut battery = BatteryMonitor::new(
+                            id.clone(),
+                            bus.clone(),
+                            settings.lock().await.low_battery_percent,
+                            info.battery,
+                        );
+                        if let Some(level) = characteristics.iter().find(|c| {
+                            c.uuid == BATTERY_LEVEL && c.properties.contains(CharPropFlags::NOTIFY)
+                        }) {
+                            if let Err(err) = peripheral.subscribe(level).await {
+                                error!("Error subscribing to the battery level: {}", err);
+                            }
+                        }
+
+                        let (mode, configs, write_config) = {
+                            let settings = settings.lock().await;
+                            (
+                                settings.channel_mode,
+                                settings.channels.clone(),
+                                settings.write.clone(),
+                            )
+                        };
+                        let mut channels = HashMap::new();
+                        for config in configs {
+                            let characteristic = match characteristics.iter().find(|c| {
+                                c.service_uuid == config.service_uuid
+                                    && c.uuid == config.characteristic_uuid
+                            }) {
+                                Some(characteristic) => characteristic,
+                                None => {
+                                    debug!("{} has no {} channel", id, config.name);
+                                    continue;
+                                }
+                            };
+                            if let Some(poll) = &config.poll {
+                                debug!("Polling the {} channel", config.name);
+                                polls.push((characteristic.clone(), poll.clone()));
+                                channels.insert(characteristic.uuid, config);
+                                continue;
+                            }
+                            let kind = if characteristic.properties.contains(CharPropFlags::NOTIFY)
+                            {
+                                "notifications"
+                            } else if characteristic.properties.contains(CharPropFlags::INDICATE) {
+                                "indications"
+                            } else {
+                                error!("The {} channel can't be subscribed to", config.name);
+                                continue;
+                            };
+                            if let Err(err) = peripheral.subscribe(characteristic).await {
+                                error!("Error subscribing to the {} channel: {}", config.name, err);
+                                device_counters.errors.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                            debug!("Subscribed to {} of the {} channel", kind, config.name);
+                            channels.insert(characteristic.uuid, config);
+                        }
+
+                        // Battery level changes, the channels and polled
+                        // values arrive on the same stream as the data.
+                        let (polled, poll_tasks) =
+                            poll::spawn(&peripheral, polls, device_counters.late_polls.clone());
+                        let notifications = stream::select(notifications, polled);
+                        let primary = rx_characteristic.uuid;
+                        let channel_bus = bus.clone();
+                        let channel_id = id.clone();
+                        let channel_counters = device_counters.clone();
+                        let notifications = notifications.filter_map(move |notification| {
+                            if notification.uuid == BATTERY_LEVEL {
+                                battery.update(&notification.value);
+                                return future::ready(None);
+                            }
+                            let tag = match channels.get(&notification.uuid) {
+                                Some(config) if mode == ChannelMode::Separate => {
+                                    channel_bus.publish(BleEvent::ChannelNotification(
+                                        ChannelData {
+                                            id: channel_id.clone(),
+                                            channel: config.name.clone(),
+                                            data: notification.value,
+                                        },
+                                    ));
+                                    return future::ready(None);
                                 }
+                                Some(config) => config.tag,
+                                None if notification.uuid == primary => PRIMARY_TAG,
+                                None => return future::ready(None),
+                            };
+                            if mode == ChannelMode::Separate {
+                                return future::ready(Some(notification));
                             }
+                            // Tagged by the bridge once framed, so each
+                            // characteristic is reassembled on its own.
+                            channel_counters.count_notification(notification.value.len());
+                            channel_bus.publish(BleEvent::MultiplexedNotification(TaggedData {
+                                id: channel_id.clone(),
+                                tag,
+                                data: notification.value,
+                            }));
+                            future::ready(None)
                         });
 
-                        if let Err(err) = device_tx.send(BleEvent::DeviceConnected(device)).await {
-                            error!("Error sending device connected event: {:?}", err);
+                        let writer = profile.write_characteristic(&characteristics).cloned().map(
+                            |characteristic| {
+                                WriteQueue::spawn(
+                                    peripheral.clone(),
+                                    characteristic,
+                                    write_config,
+                                    device_counters.writes.clone(),
+                                )
+                            },
+                        );
+                        let session = SessionHandle::forward(
+                            id,
+                            Some(rx_characteristic),
+                            notifications,
+                            bus.clone(),
+                            device_counters,
+                        )
+                        .with_writer(writer)
+                        .with_tasks(poll_tasks);
+
+                        device.profile = Some(profile.name);
+                        device.info = Some(info);
+                        connections.connected(session, device).await;
+                    }
+                    CentralEvent::ManufacturerDataAdvertisement {
+                        id,
+                        manufacturer_data,
+                    } => {
+                        let config = match settings.lock().await.advertisement.clone() {
+                            Some(config) => config,
+                            None => continue,
+                        };
+                        let payloads =
+                            config.payloads(Advertisement::Manufacturer(&manufacturer_data));
+                        if let Err(err) = forward_advertisement(
+                            &central_copy,
+                            &devices_clone,
+                            &config,
+                            &mut dedup,
+                            &bus,
+                            &id,
+                            payloads,
+                        )
+                        .await
+                        {
+                            error!("Error forwarding advertisement: {}", err);
+                            event_loop_errors.fetch_add(1, Ordering::Relaxed);
                         }
                     }
-                    CentralEvent::DeviceDisconnected(id) => {
-                        if let Err(err) = device_tx.send(BleEvent::DeviceDisconnected).await {
-                            error!("Error sending device disconnect event: {:?}", err);
+                    CentralEvent::ServiceDataAdvertisement { id, service_data } => {
+                        let config = match settings.lock().await.advertisement.clone() {
+                            Some(config) => config,
+                            None => continue,
+                        };
+                        let payloads = config.payloads(Advertisement::Service(&service_data));
+                        if let Err(err) = forward_advertisement(
+                            &central_copy,
+                            &devices_clone,
+                            &config,
+                            &mut dedup,
+                            &bus,
+                            &id,
+                            payloads,
+                        )
+                        .await
+                        {
+                            error!("Error forwarding advertisement: {}", err);
+                            event_loop_errors.fetch_add(1, Ordering::Relaxed);
                         }
                     }
+                    CentralEvent::DeviceDisconnected(id) => {
+                        connections.disconnected(&id).await;
+                    }
                     _ => {}
                 }
             }
@@ -175,9 +795,10 @@ impl BleManager {
         Ok(())
     }
 
-    pub async fn start_scan(&mut self) -> Result<(), Box<dyn Error>> {
+    pub async fn start_scan(&self) -> Result<(), Box<dyn Error>> {
         self.stop_scan().await?;
         self.central.start_scan(Default::default()).await?;
+        self.scanning.store(true, Ordering::Relaxed);
         Ok(())
     }
 
@@ -192,42 +813,435 @@ impl BleManager {
     }
 
     pub async fn connect_device(&self, id: String) -> Result<(), Box<dyn Error>> {
-        let peripheral_id = self.devices.lock().await;
-        let peripheral_id = peripheral_id.get(&id).ok_or("Device not found")?;
+        let central = &self.central;
+        self.devices
+            .with_handle(&id, |peripheral_id| async move {
+                let peripheral = central.peripheral(&peripheral_id).await?;
+                peripheral.connect().await?;
+                Ok::<_, Box<dyn Error>>(())
+            })
+            .await
+    }
+
+    /// Makes the device at `address` connectable without it passing discovery
+    /// or the name filter. The OS cache is tried first, then the adapter scans
+    /// for up to `timeout`. Returns the device id, `None` when it wasn't seen.
+    pub async fn locate(
+        &self,
+        address: &str,
+        timeout: Duration,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let address: BDAddr = address.parse()?;
+        let id = address.to_string();
+        if self.devices.get(&id).await.is_some() {
+            return Ok(Some(id));
+        }
 
-        let peripheral = self.central.peripheral(&peripheral_id.0).await?;
-        peripheral.connect().await?;
+        // Subscribed before looking at the cache so an advertisement in between isn't missed.
+        let mut events = self.central.events().await?;
+        let cached = self
+            .central
+            .peripherals()
+            .await?
+            .into_iter()
+            .find(|peripheral| peripheral.address() == address);
+        let peripheral = match cached {
+            Some(peripheral) => Some(peripheral),
+            None => {
+                debug!("Scanning for {}", id);
+                // A running scan reports the device as well, and must go on
+                // once it's found.
+                let own_scan = !self.scanning.load(Ordering::Relaxed);
+                if own_scan {
+                    self.central.start_scan(ScanFilter::default()).await?;
+                }
+                let central = &self.central;
+                let found = tokio::time::timeout(timeout, async {
+                    while let Some(event) = events.next().await {
+                        if let CentralEvent::DeviceDiscovered(peripheral_id)
+                        | CentralEvent::DeviceUpdated(peripheral_id) = event
+                        {
+                            match central.peripheral(&peripheral_id).await {
+                                Ok(peripheral) if peripheral.address() == address => {
+                                    return Some(peripheral)
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    None
+                })
+                .await;
+                // Unless someone started scanning in the meantime.
+                if own_scan && !self.scanning.load(Ordering::Relaxed) {
+                    if let Err(err) = self.central.stop_scan().await {
+                        error!("Error stopping scan: {}", err);
+                    }
+                }
+                found.ok().flatten()
+            }
+        };
+
+        match peripheral {
+            Some(peripheral) => {
+                self.remember(&peripheral).await?;
+                Ok(Some(id))
+            }
+            None => Ok(None),
+        }
+    }
 
+    /// Adds a peripheral found outside the event loop to the device table.
+    async fn remember(&self, peripheral: &PlatformPeripheral) -> Result<(), Box<dyn Error>> {
+        let id = peripheral.address().to_string();
+        let mut device = match peripheral.properties().await? {
+            Some(properties) => {
+                let name = properties.local_name.clone().unwrap_or_default();
+                Device::from_properties(id, name, properties)
+            }
+            None => Device {
+                id,
+                name: String::new(),
+                rssi: None,
+                tx_power: None,
+                last_seen: now_millis(),
+                services: Vec::new(),
+                alias: None,
+                color: None,
+                notes: None,
+                profile: None,
+                info: None,
+            },
+        };
+        device.annotate(self.known.lock().await.get(&device.id));
+        self.devices.insert(peripheral.id(), device).await;
         Ok(())
     }
 
+    /// Connects to `address`, scanning for it first when needed; see [`Self::locate`].
+    pub async fn connect_address(
+        &self,
+        address: &str,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn Error>> {
+        let id = self
+            .locate(address, timeout)
+            .await?
+            .ok_or_else(|| format!("Device {} not found", address))?;
+        self.connect_device(id.clone()).await?;
+        Ok(id)
+    }
+
     pub async fn stop_scan(&self) -> Result<(), Box<dyn Error>> {
+        self.scanning.store(false, Ordering::Relaxed);
         self.central.stop_scan().await?;
 
         Ok(())
     }
 
     pub async fn disconnect_device(&self, id: String) -> Result<(), Box<dyn Error>> {
-        let peripheral_id = self.devices.lock().await;
-        let peripheral_id = peripheral_id.get(&id).ok_or("Device not found")?;
+        let central = &self.central;
+        let sessions = &self.sessions;
+        let id = &id;
+        self.devices
+            .with_handle(id, |peripheral_id| async move {
+                let peripheral = central.peripheral(&peripheral_id).await?;
+                // Stop forwarding right away rather than when the disconnect event arrives.
+                sessions.lock().await.close(id).await;
+                peripheral.disconnect().await?;
+                Ok::<_, Box<dyn Error>>(())
+            })
+            .await
+    }
 
-        let peripheral = self.central.peripheral(&peripheral_id.0).await?;
-        peripheral.disconnect().await?;
+    /// The latest advertisement of a discovered device.
+    pub async fn get_advertisement(&self, id: &str) -> Result<AdvertisementData, Box<dyn Error>> {
+        let central = &self.central;
+        self.devices
+            .with_handle(id, |peripheral_id| async move {
+                let peripheral = central.peripheral(&peripheral_id).await?;
+                let properties = peripheral
+                    .properties()
+                    .await?
+                    .ok_or("No advertisement received")?;
+                Ok::<_, Box<dyn Error>>(AdvertisementData::from_properties(
+                    id.to_string(),
+                    &properties,
+                ))
+            })
+            .await
+    }
 
-        Ok(())
+    /// Lists the services and characteristics of a connected device.
+    pub async fn gatt(&self, id: &str) -> Result<Vec<GattService>, Box<dyn Error>> {
+        let central = &self.central;
+        let peripheral = self
+            .devices
+            .with_handle(id, |peripheral_id| async move {
+                let peripheral = central.peripheral(&peripheral_id).await?;
+                peripheral.discover_services().await?;
+                Ok::<_, Box<dyn Error>>(peripheral)
+            })
+            .await?;
+
+        let services = peripheral
+            .services()
+            .into_iter()
+            .map(|service| GattService {
+                uuid: service.uuid,
+                primary: service.primary,
+                characteristics: service
+                    .characteristics
+                    .into_iter()
+                    .map(|c| GattCharacteristic {
+                        uuid: c.uuid,
+                        properties: PROPERTY_NAMES
+                            .iter()
+                            .filter(|(flag, _)| c.properties.contains(*flag))
+                            .map(|(_, name)| name.to_string())
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(services)
+    }
+
+    /// Stops scanning, then unsubscribes and disconnects every connected
+    /// device. Each step gives up after [`SHUTDOWN_TIMEOUT`].
+    pub async fn shutdown(&self) {
+        if let Err(err) = self.stop_scan().await {
+            error!("Error stopping scan: {}", err);
+        }
+
+        let devices = self.devices.handles().await;
+        let mut sessions = self.sessions.lock().await;
+
+        for (id, peripheral_id) in devices {
+            let session = sessions.take(&id);
+            let peripheral = match self.central.peripheral(&peripheral_id).await {
+                Ok(peripheral) => peripheral,
+                Err(_) => {
+                    if let Some(session) = session {
+                        session.close().await;
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(session) = session {
+                if let Some(characteristic) = session.characteristic() {
+                    match timeout(SHUTDOWN_TIMEOUT, peripheral.unsubscribe(characteristic)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => error!("Error unsubscribing {}: {}", id, err),
+                        Err(_) => error!("Timed out unsubscribing {}", id),
+                    }
+                }
+                session.close().await;
+            }
+
+            if !matches!(
+                timeout(SHUTDOWN_TIMEOUT, peripheral.is_connected()).await,
+                Ok(Ok(true))
+            ) {
+                continue;
+            }
+            debug!("Disconnecting {}", id);
+            match timeout(SHUTDOWN_TIMEOUT, peripheral.disconnect()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => error!("Error disconnecting {}: {}", id, err),
+                Err(_) => error!("Timed out disconnecting {}", id),
+            }
+        }
+
+        sessions.close_all().await;
+    }
+
+    pub async fn counters(&self) -> HashMap<String, Arc<DeviceCounters>> {
+        self.counters.lock().await.clone()
+    }
+
+    /// Queues `data` to be written to a connected device.
+    pub async fn write(&self, id: &str, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        self.sessions.lock().await.write(id, data)
+    }
+
+    pub async fn write_stats(&self) -> HashMap<String, WriteStats> {
+        self.counters
+            .lock()
+            .await
+            .iter()
+            .map(|(id, counters)| (id.clone(), counters.writes.stats()))
+            .collect()
+    }
+
+    pub fn event_loop_errors(&self) -> u64 {
+        self.event_loop_errors.load(Ordering::Relaxed)
     }
 
     pub async fn get_devices(&self) -> Result<Vec<Device>, Box<dyn Error>> {
-        let devices = self.devices.lock().await;
-        let devices = devices
-            .clone()
-            .into_iter()
-            .map(|(_, device)| Device {
-                id: device.1.id.clone(),
-                name: device.1.name.clone(),
+        Ok(self.devices.list().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use btleplug::api::ValueNotification;
+    use futures::channel::mpsc::unbounded;
+
+    use super::*;
+    use crate::bus::{Received, Subscription, Topic};
+
+    fn device(id: &str) -> Device {
+        Device {
+            id: id.to_string(),
+            name: "BioSignal".to_string(),
+            rssi: None,
+            tx_power: None,
+            last_seen: 0,
+            services: Vec::new(),
+            alias: None,
+            color: None,
+            notes: None,
+            profile: None,
+            info: None,
+        }
+    }
+
+    fn seen(id: &str, rssi: Option<i16>, last_seen: u64) -> Device {
+        Device {
+            rssi,
+            last_seen,
+            ..device(id)
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_connect_does_not_block_get_devices() {
+        let devices = Arc::new(DeviceTable::<u32>::default());
+        devices.insert(1, device("A")).await;
+        devices.insert(2, device("B")).await;
+
+        // Stands in for a peripheral that takes ages to accept the connection.
+        let connecting = {
+            let devices = devices.clone();
+            tokio::spawn(async move {
+                devices
+                    .with_handle("A", |_| async {
+                        tokio::time::sleep(Duration::from_secs(30)).await;
+                        Ok(())
+                    })
+                    .await
+                    .is_ok()
             })
-            .collect::<Vec<Device>>();
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!connecting.is_finished());
+
+        let listed = tokio::time::timeout(Duration::from_millis(100), devices.list())
+            .await
+            .expect("listing devices waited for the connect");
+        assert_eq!(listed.len(), 2);
+        assert_eq!(devices.id_of(&2).await.as_deref(), Some("B"));
+
+        connecting.abort();
+    }
+
+    #[tokio::test]
+    async fn devices_are_listed_by_signal_strength() {
+        let devices = DeviceTable::<u32>::default();
+        assert!(devices.insert(1, seen("A", Some(-80), 0)).await);
+        assert!(devices.insert(2, seen("B", None, 0)).await);
+        assert!(devices.insert(3, seen("C", Some(-40), 0)).await);
+        assert!(!devices.insert(1, seen("A", Some(-30), 0)).await);
+
+        let ids: Vec<String> = devices.list().await.into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, ["A", "C", "B"]);
+    }
+
+    #[tokio::test]
+    async fn stale_devices_are_removed_unless_kept() {
+        let devices = DeviceTable::<u32>::default();
+        devices.insert(1, seen("A", None, 1_000)).await;
+        devices.insert(2, seen("B", None, 5_000)).await;
+        devices.insert(3, seen("C", None, 1_000)).await;
+
+        let keep = HashSet::from(["C".to_string()]);
+        assert_eq!(devices.remove_stale(2_000, &keep).await, ["A"]);
+        assert!(devices.get("A").await.is_none());
+        assert_eq!(devices.list().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unknown_device_is_an_error() {
+        let devices = DeviceTable::<u32>::default();
+        let result = devices.with_handle("A", |_| async { Ok(()) }).await;
+        assert!(result.is_err());
+    }
+
+    /// The next event, if one comes within a moment.
+    async fn next_event(events: &mut Subscription) -> Option<Arc<BleEvent>> {
+        match tokio::time::timeout(Duration::from_millis(50), events.recv()).await {
+            Ok(Some(Received::Event(event))) => Some(event),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnect_ends_the_session_of_the_connected_device() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe(&[Topic::Lifecycle, Topic::Data]);
+        let connections = Connections {
+            devices: Arc::new(DeviceTable::<u32>::default()),
+            counters: Default::default(),
+            sessions: Default::default(),
+            bus: bus.clone(),
+        };
+        connections.devices.insert(1, device("A")).await;
+
+        let counters = connections.connecting("A").await;
+        let (tx, rx) = unbounded();
+        let session =
+            SessionHandle::forward("A".to_string(), None, rx, bus.clone(), counters.clone());
+        connections.connected(session, device("A")).await;
+
+        assert!(counters.connected.load(Ordering::Relaxed));
+        match next_event(&mut events).await.as_deref() {
+            Some(BleEvent::DeviceConnected(device)) => assert_eq!(device.id, "A"),
+            event => panic!("expected a connection, got {:?}", event),
+        }
+        tx.unbounded_send(ValueNotification {
+            uuid: Uuid::nil(),
+            value: vec![1],
+        })
+        .unwrap();
+        match next_event(&mut events).await.as_deref() {
+            Some(BleEvent::DeviceNotification(id, data)) => {
+                assert_eq!(id, "A");
+                assert_eq!(data, &vec![1]);
+            }
+            event => panic!("expected a notification, got {:?}", event),
+        }
+
+        // A device that isn't listed is left alone.
+        connections.disconnected(&2).await;
+        assert!(next_event(&mut events).await.is_none());
+        assert!(connections.sessions.lock().await.contains("A"));
+
+        connections.disconnected(&1).await;
+        assert!(!counters.connected.load(Ordering::Relaxed));
+        assert!(tx.is_closed());
+        assert!(connections.sessions.lock().await.is_empty());
+        match next_event(&mut events).await.as_deref() {
+            Some(BleEvent::DeviceDisconnected(id)) => assert_eq!(id, "A"),
+            event => panic!("expected a disconnection, got {:?}", event),
+        }
 
-        Ok(devices)
+        // Reconnecting keeps counting on the same counters.
+        assert!(Arc::ptr_eq(&connections.connecting("A").await, &counters));
+        assert_eq!(counters.connections.load(Ordering::Relaxed), 2);
     }
 }
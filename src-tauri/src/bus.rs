@@ -0,0 +1,146 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::future::select_all;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::ble::BleEvent;
+
+const LIFECYCLE_CAPACITY: usize = 100;
+const DATA_CAPACITY: usize = 1024;
+const ERRORS_CAPACITY: usize = 100;
+const ADVERTISEMENTS_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Topic {
+    /// Discovery, connection and disconnection of devices.
+    Lifecycle,
+    /// Notification payloads.
+    Data,
+    /// Device errors and frame integrity problems.
+    Errors,
+    /// Raw advertisement fields of listed devices.
+    Advertisements,
+}
+
+impl Topic {
+    pub fn of(event: &BleEvent) -> Self {
+        match event {
+            BleEvent::DeviceDiscovered(_)
+            | BleEvent::DeviceConnected(_)
+            | BleEvent::DeviceDisconnected(_)
+            | BleEvent::DeviceUpdated(_)
+            | BleEvent::DeviceLost(_)
+            | BleEvent::BatteryLevel(_)
+            | BleEvent::BatteryLow(_) => Topic::Lifecycle,
+            BleEvent::DeviceNotification(..)
+            | BleEvent::ChannelNotification(_)
+            | BleEvent::MultiplexedNotification(_) => Topic::Data,
+            BleEvent::DeviceError(_) | BleEvent::FrameLoss(_) | BleEvent::CrcError(_) => {
+                Topic::Errors
+            }
+            BleEvent::DeviceAdvertisement(_) => Topic::Advertisements,
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "lifecycle" => Ok(Topic::Lifecycle),
+            "data" => Ok(Topic::Data),
+            "errors" => Ok(Topic::Errors),
+            "advertisements" => Ok(Topic::Advertisements),
+            _ => Err(format!("Unknown topic {}", name)),
+        }
+    }
+}
+
+pub enum Received {
+    Event(Arc<BleEvent>),
+    /// The subscriber fell behind and `missed` events on `topic` were dropped for it.
+    Lagged {
+        topic: Topic,
+        missed: u64,
+    },
+}
+
+/// Publish/subscribe hub between the BLE layer, the bridge and the UI.
+///
+/// Publishing never waits; a subscriber that can't keep up loses the oldest
+/// events and is told so with [`Received::Lagged`].
+#[derive(Clone)]
+pub struct EventBus {
+    lifecycle: broadcast::Sender<Arc<BleEvent>>,
+    data: broadcast::Sender<Arc<BleEvent>>,
+    errors: broadcast::Sender<Arc<BleEvent>>,
+    advertisements: broadcast::Sender<Arc<BleEvent>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            lifecycle: broadcast::channel(LIFECYCLE_CAPACITY).0,
+            data: broadcast::channel(DATA_CAPACITY).0,
+            errors: broadcast::channel(ERRORS_CAPACITY).0,
+            advertisements: broadcast::channel(ADVERTISEMENTS_CAPACITY).0,
+        }
+    }
+
+    fn sender(&self, topic: Topic) -> &broadcast::Sender<Arc<BleEvent>> {
+        match topic {
+            Topic::Lifecycle => &self.lifecycle,
+            Topic::Data => &self.data,
+            Topic::Errors => &self.errors,
+            Topic::Advertisements => &self.advertisements,
+        }
+    }
+
+    pub fn publish(&self, event: BleEvent) {
+        // Sending only fails when nobody is subscribed, which is fine.
+        let _ = self.sender(Topic::of(&event)).send(Arc::new(event));
+    }
+
+    /// Attaches a subscriber to `topics`; dropping the subscription detaches it.
+    pub fn subscribe(&self, topics: &[Topic]) -> Subscription {
+        Subscription {
+            receivers: topics
+                .iter()
+                .map(|topic| (*topic, self.sender(*topic).subscribe()))
+                .collect(),
+        }
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Subscription {
+    receivers: Vec<(Topic, broadcast::Receiver<Arc<BleEvent>>)>,
+}
+
+impl Subscription {
+    /// Waits for the next event on any subscribed topic, `None` once the bus is gone.
+    pub async fn recv(&mut self) -> Option<Received> {
+        if self.receivers.is_empty() {
+            return None;
+        }
+
+        let receives = self.receivers.iter_mut().map(|(topic, rx)| {
+            let topic = *topic;
+            Box::pin(async move { (topic, rx.recv().await) })
+        });
+        let ((topic, result), _, _) = select_all(receives).await;
+
+        match result {
+            Ok(event) => Some(Received::Event(event)),
+            Err(RecvError::Lagged(missed)) => Some(Received::Lagged { topic, missed }),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use uuid::Uuid;
+
+use crate::poll::PollConfig;
+use crate::sink::SinkConfig;
+
+/// Bytes in front of each multiplexed notification: the channel tag (1) and
+/// the payload length (2, little-endian).
+pub const MUX_HEADER_LEN: usize = 3;
+
+/// Tag of the profile's own characteristic when multiplexing.
+pub const PRIMARY_TAG: u8 = 0;
+
+/// How data of the extra characteristics reaches the outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ChannelMode {
+    /// Each channel goes to its own output; the bridge only gets the
+    /// profile's characteristic.
+    #[default]
+    Separate,
+    /// Every characteristic, the profile's included, goes to the bridge, which
+    /// frames each on its own and puts a [`MUX_HEADER_LEN`] byte header naming
+    /// the channel in front of every frame.
+    Multiplexed,
+}
+
+/// A characteristic subscribed to next to the profile's one. It is
+/// subscribed with notifications, or indications when it only has those,
+/// unless it is polled.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChannelConfig {
+    pub name: String,
+    pub service_uuid: Uuid,
+    pub characteristic_uuid: Uuid,
+    /// Identifies the channel in multiplexed data; must not be [`PRIMARY_TAG`].
+    pub tag: u8,
+    /// Where the channel's data goes when separate.
+    #[serde(default)]
+    pub output: Option<SinkConfig>,
+    /// Read the characteristic periodically instead of subscribing.
+    #[serde(default)]
+    pub poll: Option<PollConfig>,
+}
+
+/// `payload` preceded by the multiplexing header.
+pub fn tagged(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(MUX_HEADER_LEN + payload.len());
+    data.push(tag);
+    data.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Data of a separate channel.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChannelData {
+    pub id: String,
+    pub channel: String,
+    pub data: Vec<u8>,
+}
+
+/// Data of one characteristic when multiplexing, not yet framed or tagged.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TaggedData {
+    pub id: String,
+    pub tag: u8,
+    pub data: Vec<u8>,
+}
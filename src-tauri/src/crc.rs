@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+
+use crate::inspector::Endianness;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CrcWidth {
+    Crc8,
+    Crc16,
+    Crc32,
+}
+
+impl CrcWidth {
+    fn bits(self) -> u32 {
+        match self {
+            CrcWidth::Crc8 => 8,
+            CrcWidth::Crc16 => 16,
+            CrcWidth::Crc32 => 32,
+        }
+    }
+
+    fn bytes(self) -> usize {
+        self.bits() as usize / 8
+    }
+}
+
+/// Where the checksum sits in the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum CrcLocation {
+    /// The last bytes of the frame.
+    #[default]
+    End,
+    /// A fixed offset from the start of the frame.
+    Offset(usize),
+}
+
+/// What to do with a frame whose checksum doesn't match.
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum BadFramePolicy {
+    #[default]
+    Forward,
+    Drop,
+    /// Forward the frame preceded by these marker bytes.
+    Mark(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CrcConfig {
+    pub width: CrcWidth,
+    pub polynomial: u32,
+    #[serde(default)]
+    pub init: u32,
+    #[serde(default)]
+    pub xor_out: u32,
+    /// Reflect input bytes and the final value, as in CRC-32 or CRC-16/MODBUS.
+    #[serde(default)]
+    pub reflect: bool,
+    #[serde(default)]
+    pub location: CrcLocation,
+    /// First byte covered by the checksum, e.g. to skip a sync byte.
+    #[serde(default)]
+    pub start: usize,
+    /// Byte order of the stored checksum.
+    #[serde(default)]
+    pub endianness: Endianness,
+    /// Policy for devices without their own.
+    #[serde(default)]
+    pub policy: BadFramePolicy,
+}
+
+impl CrcConfig {
+    fn compute(&self, data: &[u8]) -> u32 {
+        let bits = self.width.bits();
+        let mask = u32::MAX >> (32 - bits);
+        let top = 1u32 << (bits - 1);
+        let polynomial = self.polynomial & mask;
+
+        let mut crc = self.init & mask;
+        for byte in data {
+            let byte = if self.reflect {
+                byte.reverse_bits()
+            } else {
+                *byte
+            };
+            crc ^= (byte as u32) << (bits - 8);
+            for _ in 0..8 {
+                crc = if crc & top != 0 {
+                    (crc << 1) ^ polynomial
+                } else {
+                    crc << 1
+                };
+                crc &= mask;
+            }
+        }
+
+        if self.reflect {
+            crc = crc.reverse_bits() >> (32 - bits);
+        }
+        (crc ^ self.xor_out) & mask
+    }
+
+    /// Returns `None` when the frame is too short to carry a checksum.
+    fn check(&self, frame: &[u8]) -> Option<bool> {
+        let size = self.width.bytes();
+        let offset = match self.location {
+            CrcLocation::End => frame.len().checked_sub(size)?,
+            CrcLocation::Offset(offset) => offset,
+        };
+        let stored = self.endianness.read(frame.get(offset..offset + size)?);
+        let covered = frame.get(self.start..offset)?;
+        Some(self.compute(covered) == stored)
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct CrcStats {
+    pub valid: u64,
+    pub invalid: u64,
+}
+
+/// Emitted for every frame that fails its checksum.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CrcError {
+    pub id: String,
+    pub stats: CrcStats,
+}
+
+pub enum CrcVerdict {
+    Valid,
+    Invalid(BadFramePolicy, CrcError),
+}
+
+/// Per-device checksum validation for reassembled frames.
+#[derive(Default)]
+pub struct CrcCheckers {
+    config: Option<CrcConfig>,
+    policies: HashMap<String, BadFramePolicy>,
+    stats: HashMap<String, CrcStats>,
+}
+
+impl CrcCheckers {
+    pub fn set_config(&mut self, config: Option<CrcConfig>) {
+        self.config = config;
+        self.stats.clear();
+    }
+
+    /// Policies of the devices that don't use the configured one, by id.
+    pub fn set_policies(&mut self, policies: HashMap<String, BadFramePolicy>) {
+        self.policies = policies;
+    }
+
+    pub fn reset(&mut self, id: &str) {
+        self.stats.remove(id);
+    }
+
+    pub fn stats(&self) -> HashMap<String, CrcStats> {
+        self.stats.clone()
+    }
+
+    pub fn check(&mut self, id: &str, frame: &[u8]) -> CrcVerdict {
+        let config = match &self.config {
+            Some(config) => config,
+            None => return CrcVerdict::Valid,
+        };
+
+        let stats = self.stats.entry(id.to_string()).or_default();
+        if config.check(frame) == Some(true) {
+            stats.valid += 1;
+            return CrcVerdict::Valid;
+        }
+        stats.invalid += 1;
+
+        let policy = self.policies.get(id).unwrap_or(&config.policy).clone();
+        CrcVerdict::Invalid(
+            policy,
+            CrcError {
+                id: id.to_string(),
+                stats: stats.clone(),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+    const CHECK: &[u8] = b"123456789";
+
+    fn crc8() -> CrcConfig {
+        CrcConfig {
+            width: CrcWidth::Crc8,
+            polynomial: 0x07,
+            init: 0,
+            xor_out: 0,
+            reflect: false,
+            location: CrcLocation::End,
+            start: 0,
+            endianness: Endianness::Little,
+            policy: BadFramePolicy::Forward,
+        }
+    }
+
+    fn modbus() -> CrcConfig {
+        CrcConfig {
+            width: CrcWidth::Crc16,
+            polynomial: 0x8005,
+            init: 0xFFFF,
+            reflect: true,
+            ..crc8()
+        }
+    }
+
+    fn crc32() -> CrcConfig {
+        CrcConfig {
+            width: CrcWidth::Crc32,
+            polynomial: 0x04C1_1DB7,
+            init: 0xFFFF_FFFF,
+            xor_out: 0xFFFF_FFFF,
+            reflect: true,
+            ..crc8()
+        }
+    }
+
+    /// `CHECK` followed by its checksum in little-endian order.
+    fn framed(config: &CrcConfig) -> Vec<u8> {
+        let crc = config.compute(CHECK);
+        let mut frame = CHECK.to_vec();
+        frame.extend_from_slice(&crc.to_le_bytes()[..config.width.bytes()]);
+        frame
+    }
+
+    #[test]
+    fn check_values_match_the_catalogue() {
+        assert_eq!(crc8().compute(CHECK), 0xF4);
+        assert_eq!(modbus().compute(CHECK), 0x4B37);
+        assert_eq!(crc32().compute(CHECK), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn checksum_at_the_end_is_checked() {
+        for config in [crc8(), modbus(), crc32()] {
+            let mut frame = framed(&config);
+            assert_eq!(config.check(&frame), Some(true));
+            frame[0] ^= 1;
+            assert_eq!(config.check(&frame), Some(false));
+        }
+    }
+
+    #[test]
+    fn big_endian_checksum_is_read_as_such() {
+        let config = CrcConfig {
+            endianness: Endianness::Big,
+            ..modbus()
+        };
+        let mut frame = CHECK.to_vec();
+        frame.extend_from_slice(&[0x4B, 0x37]);
+        assert_eq!(config.check(&frame), Some(true));
+    }
+
+    #[test]
+    fn checksum_at_an_offset_covers_from_start() {
+        let config = CrcConfig {
+            location: CrcLocation::Offset(10),
+            start: 1,
+            ..crc8()
+        };
+        // Sync byte, data, checksum, then a trailer the checksum doesn't cover.
+        let mut frame = vec![0xAA];
+        frame.extend_from_slice(CHECK);
+        frame.extend_from_slice(&[0xF4, 0x55]);
+        assert_eq!(config.check(&frame), Some(true));
+
+        frame[0] = 0xBB;
+        frame[11] = 0x66;
+        assert_eq!(config.check(&frame), Some(true));
+        frame[1] ^= 1;
+        assert_eq!(config.check(&frame), Some(false));
+    }
+
+    #[test]
+    fn frame_too_short_for_the_checksum_is_not_checked() {
+        assert_eq!(crc32().check(&[1, 2, 3]), None);
+        let config = CrcConfig {
+            location: CrcLocation::Offset(4),
+            start: 1,
+            ..modbus()
+        };
+        assert_eq!(config.check(&[1, 2, 3, 4, 5]), None);
+        let config = CrcConfig { start: 5, ..crc8() };
+        assert_eq!(config.check(&[1, 2, 3]), None);
+    }
+
+    fn verdict(checkers: &mut CrcCheckers, frame: &[u8]) -> Option<BadFramePolicy> {
+        match checkers.check(DEVICE, frame) {
+            CrcVerdict::Valid => None,
+            CrcVerdict::Invalid(policy, error) => {
+                assert_eq!(error.id, DEVICE);
+                Some(policy)
+            }
+        }
+    }
+
+    #[test]
+    fn bad_frames_get_the_device_policy_or_the_configured_one() {
+        let mut checkers = CrcCheckers::default();
+        let good = framed(&crc8());
+        let bad = [1, 2, 3];
+        assert_eq!(verdict(&mut checkers, &bad), None);
+
+        checkers.set_config(Some(CrcConfig {
+            policy: BadFramePolicy::Drop,
+            ..crc8()
+        }));
+        assert_eq!(verdict(&mut checkers, &good), None);
+        assert_eq!(verdict(&mut checkers, &bad), Some(BadFramePolicy::Drop));
+
+        let mark = BadFramePolicy::Mark(vec![0xEE]);
+        checkers.set_policies([(DEVICE.to_string(), mark.clone())].into());
+        assert_eq!(verdict(&mut checkers, &bad), Some(mark));
+        checkers.set_policies([(DEVICE.to_string(), BadFramePolicy::Forward)].into());
+        assert_eq!(verdict(&mut checkers, &bad), Some(BadFramePolicy::Forward));
+
+        let stats = &checkers.stats()[DEVICE];
+        assert_eq!((stats.valid, stats.invalid), (1, 3));
+    }
+}
@@ -0,0 +1,348 @@
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::inspector::Endianness;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    JsonLines,
+}
+
+/// Where decoded samples are written.
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum DecodedOutput {
+    /// A second virtual port pair next to the raw one.
+    #[default]
+    Port,
+    File(PathBuf),
+}
+
+/// Layout of the samples carried in a frame.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DecoderConfig {
+    pub channels: usize,
+    /// Sample width in bytes (1 to 4).
+    pub sample_width: usize,
+    #[serde(default)]
+    pub endianness: Endianness,
+    #[serde(default)]
+    pub signed: bool,
+    /// Number of bytes before the first sample.
+    #[serde(default)]
+    pub header_len: usize,
+    #[serde(default)]
+    pub counter_offset: Option<usize>,
+    #[serde(default = "default_counter_width")]
+    pub counter_width: usize,
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub output: DecodedOutput,
+}
+
+fn default_counter_width() -> usize {
+    1
+}
+
+pub struct FrameDecoder {
+    config: DecoderConfig,
+    writer: Box<dyn Write + Send>,
+}
+
+impl FrameDecoder {
+    pub fn new(
+        config: DecoderConfig,
+        mut writer: Box<dyn Write + Send>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if config.channels == 0 {
+            return Err("Decoder needs at least one channel".into());
+        }
+        if !(1..=4).contains(&config.sample_width) {
+            return Err("Sample width must be between 1 and 4 bytes".into());
+        }
+        if !(1..=4).contains(&config.counter_width) {
+            return Err("Counter width must be between 1 and 4 bytes".into());
+        }
+
+        if let OutputFormat::Csv = config.format {
+            let mut header = String::from("timestamp,id,counter");
+            for channel in 0..config.channels {
+                header.push_str(&format!(",ch{}", channel));
+            }
+            writeln!(writer, "{}", header)?;
+        }
+
+        Ok(Self { config, writer })
+    }
+
+    /// Splits a frame into rows of channel values.
+    pub fn decode(&self, frame: &[u8]) -> (Option<u32>, Vec<Vec<i64>>) {
+        let config = &self.config;
+        let counter = config.counter_offset.and_then(|offset| {
+            frame
+                .get(offset..offset + config.counter_width)
+                .map(|bytes| config.endianness.read(bytes))
+        });
+
+        let samples = frame.get(config.header_len..).unwrap_or_default();
+        let rows = samples
+            .chunks_exact(config.channels * config.sample_width)
+            .map(|row| {
+                row.chunks_exact(config.sample_width)
+                    .map(|sample| self.sample(sample))
+                    .collect()
+            })
+            .collect();
+
+        (counter, rows)
+    }
+
+    fn sample(&self, bytes: &[u8]) -> i64 {
+        let raw = self.config.endianness.read(bytes) as i64;
+        if self.config.signed {
+            let shift = 64 - 8 * bytes.len();
+            (raw << shift) >> shift
+        } else {
+            raw
+        }
+    }
+
+    pub fn write_frame(&mut self, id: &str, frame: &[u8]) -> Result<(), Box<dyn Error>> {
+        let (counter, rows) = self.decode(frame);
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+
+        for row in rows {
+            match self.config.format {
+                OutputFormat::Csv => {
+                    let counter = counter.map(|c| c.to_string()).unwrap_or_default();
+                    let values = row
+                        .iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    writeln!(self.writer, "{},{},{},{}", timestamp, id, counter, values)?;
+                }
+                OutputFormat::JsonLines => {
+                    let line = serde_json::json!({
+                        "timestamp": timestamp,
+                        "id": id,
+                        "counter": counter,
+                        "channels": row,
+                    });
+                    writeln!(self.writer, "{}", line)?;
+                }
+            }
+        }
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+
+    /// Collects what the decoder writes.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Capture {
+        fn lines(&self) -> Vec<String> {
+            let output = self.0.lock().unwrap();
+            String::from_utf8_lossy(&output)
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn config(channels: usize, sample_width: usize) -> DecoderConfig {
+        DecoderConfig {
+            channels,
+            sample_width,
+            endianness: Endianness::Little,
+            signed: false,
+            header_len: 0,
+            counter_offset: None,
+            counter_width: 1,
+            format: OutputFormat::Csv,
+            output: DecodedOutput::Port,
+        }
+    }
+
+    fn decoding(config: DecoderConfig) -> FrameDecoder {
+        FrameDecoder::new(config, Box::new(io::sink())).unwrap()
+    }
+
+    #[test]
+    fn signed_samples_are_sign_extended() {
+        let decoder = decoding(DecoderConfig {
+            signed: true,
+            ..config(3, 1)
+        });
+        assert_eq!(
+            decoder.decode(&[0xFF, 0x80, 0x7F]).1,
+            vec![vec![-1, -128, 127]]
+        );
+
+        let decoder = decoding(DecoderConfig {
+            signed: true,
+            ..config(2, 2)
+        });
+        assert_eq!(
+            decoder.decode(&[0xFE, 0xFF, 0x00, 0x80]).1,
+            vec![vec![-2, -32768]]
+        );
+
+        let decoder = decoding(DecoderConfig {
+            signed: true,
+            ..config(1, 4)
+        });
+        assert_eq!(
+            decoder.decode(&[0, 0, 0, 0x80]).1,
+            vec![vec![i32::MIN as i64]]
+        );
+
+        let unsigned = decoding(config(1, 2));
+        assert_eq!(unsigned.decode(&[0xFE, 0xFF]).1, vec![vec![0xFFFE]]);
+    }
+
+    #[test]
+    fn samples_follow_the_endianness() {
+        let frame = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+        let little = decoding(config(1, 3));
+        assert_eq!(
+            little.decode(&frame).1,
+            vec![vec![0x030201], vec![0x060504]]
+        );
+
+        let big = decoding(DecoderConfig {
+            endianness: Endianness::Big,
+            ..config(1, 3)
+        });
+        assert_eq!(big.decode(&frame).1, vec![vec![0x010203], vec![0x040506]]);
+    }
+
+    #[test]
+    fn counter_is_read_from_the_header() {
+        let decoder = decoding(DecoderConfig {
+            endianness: Endianness::Big,
+            header_len: 3,
+            counter_offset: Some(1),
+            counter_width: 2,
+            ..config(1, 1)
+        });
+        assert_eq!(
+            decoder.decode(&[0xAA, 0x01, 0x02, 7, 8]),
+            (Some(0x0102), vec![vec![7], vec![8]])
+        );
+
+        // A frame too short for the counter still has its samples decoded.
+        let decoder = decoding(DecoderConfig {
+            counter_offset: Some(4),
+            ..config(1, 1)
+        });
+        assert_eq!(decoder.decode(&[1, 2]), (None, vec![vec![1], vec![2]]));
+    }
+
+    #[test]
+    fn partial_trailing_row_is_dropped() {
+        let decoder = decoding(DecoderConfig {
+            header_len: 1,
+            ..config(2, 2)
+        });
+        assert_eq!(
+            decoder.decode(&[0, 1, 0, 2, 0, 3, 0, 4]).1,
+            vec![vec![1, 2]]
+        );
+        assert!(decoder.decode(&[0, 1]).1.is_empty());
+        // Shorter than the header.
+        assert!(decoder.decode(&[]).1.is_empty());
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_line_per_row() {
+        let output = Capture::default();
+        let mut decoder = FrameDecoder::new(
+            DecoderConfig {
+                header_len: 1,
+                counter_offset: Some(0),
+                ..config(2, 1)
+            },
+            Box::new(output.clone()),
+        )
+        .unwrap();
+        decoder.write_frame(DEVICE, &[9, 1, 2, 3, 4]).unwrap();
+
+        let lines = output.lines();
+        assert_eq!(lines[0], "timestamp,id,counter,ch0,ch1");
+        let rows: Vec<&str> = lines[1..]
+            .iter()
+            .map(|line| line.split_once(',').unwrap().1)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![format!("{},9,1,2", DEVICE), format!("{},9,3,4", DEVICE)]
+        );
+    }
+
+    #[test]
+    fn json_lines_have_an_object_per_row() {
+        let output = Capture::default();
+        let mut decoder = FrameDecoder::new(
+            DecoderConfig {
+                signed: true,
+                format: OutputFormat::JsonLines,
+                ..config(2, 1)
+            },
+            Box::new(output.clone()),
+        )
+        .unwrap();
+        decoder.write_frame(DEVICE, &[1, 0xFF, 3]).unwrap();
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 1);
+        let mut line: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert!(line["timestamp"].is_u64());
+        line.as_object_mut().unwrap().remove("timestamp");
+        assert_eq!(
+            line,
+            json!({ "id": DEVICE, "counter": null, "channels": [1, -1] })
+        );
+    }
+
+    #[test]
+    fn bad_layout_is_rejected() {
+        let sink = || Box::new(io::sink()) as Box<dyn Write + Send>;
+        assert!(FrameDecoder::new(config(0, 1), sink()).is_err());
+        assert!(FrameDecoder::new(config(1, 5), sink()).is_err());
+        let config = DecoderConfig {
+            counter_width: 0,
+            ..config(1, 1)
+        };
+        assert!(FrameDecoder::new(config, sink()).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+
+use btleplug::api::{Characteristic, Peripheral};
+use log::{debug, error};
+use uuid::Uuid;
+
+use crate::ble::BleEvent;
+use crate::bus::EventBus;
+
+const fn uuid16(short: u16) -> Uuid {
+    Uuid::from_u128(((short as u128) << 96) | 0x0000_1000_8000_00805f9b34fb)
+}
+
+const MANUFACTURER_NAME: Uuid = uuid16(0x2a29);
+const MODEL_NUMBER: Uuid = uuid16(0x2a24);
+const SERIAL_NUMBER: Uuid = uuid16(0x2a25);
+const FIRMWARE_REVISION: Uuid = uuid16(0x2a26);
+const HARDWARE_REVISION: Uuid = uuid16(0x2a27);
+
+pub const BATTERY_LEVEL: Uuid = uuid16(0x2a19);
+
+/// What the Device Information and Battery services report, for the fields
+/// the device has.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DeviceInfo {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+    /// Percent.
+    pub battery: Option<u8>,
+}
+
+impl DeviceInfo {
+    /// Reads every field the device has; fields that fail to read are left
+    /// empty.
+    pub async fn read<P: Peripheral>(
+        peripheral: &P,
+        characteristics: &BTreeSet<Characteristic>,
+    ) -> Self {
+        let read = |uuid: Uuid| async move {
+            let characteristic = characteristics.iter().find(|c| c.uuid == uuid)?;
+            match peripheral.read(characteristic).await {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    error!("Error reading {}: {}", uuid, err);
+                    None
+                }
+            }
+        };
+        let text = |value: Vec<u8>| {
+            String::from_utf8_lossy(&value)
+                .trim_end_matches('\0')
+                .trim()
+                .to_string()
+        };
+
+        Self {
+            manufacturer: read(MANUFACTURER_NAME).await.map(text),
+            model: read(MODEL_NUMBER).await.map(text),
+            serial: read(SERIAL_NUMBER).await.map(text),
+            firmware_revision: read(FIRMWARE_REVISION).await.map(text),
+            hardware_revision: read(HARDWARE_REVISION).await.map(text),
+            battery: read(BATTERY_LEVEL)
+                .await
+                .and_then(|value| battery_level(&value)),
+        }
+    }
+}
+
+fn battery_level(value: &[u8]) -> Option<u8> {
+    value.first().map(|level| (*level).min(100))
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BatteryLevel {
+    pub id: String,
+    pub level: u8,
+}
+
+/// Publishes a device's battery level changes and warns once each time it
+/// drops below the threshold.
+pub struct BatteryMonitor {
+    id: String,
+    bus: EventBus,
+    low_percent: u8,
+    low: bool,
+}
+
+impl BatteryMonitor {
+    pub fn new(id: String, bus: EventBus, low_percent: u8, level: Option<u8>) -> Self {
+        let mut monitor = Self {
+            id,
+            bus,
+            low_percent,
+            low: false,
+        };
+        if let Some(level) = level {
+            monitor.check(level);
+        }
+        monitor
+    }
+
+    /// Handles a notification of the battery level characteristic.
+    pub fn update(&mut self, value: &[u8]) {
+        let level = match battery_level(value) {
+            Some(level) => level,
+            None => return,
+        };
+        debug!("Battery of {} at {}%", self.id, level);
+        self.bus.publish(BleEvent::BatteryLevel(BatteryLevel {
+            id: self.id.clone(),
+            level,
+        }));
+        self.check(level);
+    }
+
+    fn check(&mut self, level: u8) {
+        let low = level < self.low_percent;
+        if low && !self.low {
+            self.bus.publish(BleEvent::BatteryLow(BatteryLevel {
+                id: self.id.clone(),
+                level,
+            }));
+        }
+        self.low = low;
+    }
+}
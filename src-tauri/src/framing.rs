@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+
+use log::error;
+
+use crate::inspector::Endianness;
+use crate::profile::Profile;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// Partial frames larger than this are dropped so a lost delimiter can't grow
+/// the buffer forever.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// A complete frame cut out of the notification stream.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Bytes as they arrived over the air, delimiters and escaping included.
+    pub raw: Vec<u8>,
+    /// Decoded frame contents used for inspection and decoding.
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LengthPrefix {
+    /// Byte offset of the length field from the start of the frame.
+    #[serde(default)]
+    pub offset: usize,
+    /// Length field width in bytes (1 to 4).
+    pub width: usize,
+    #[serde(default)]
+    pub endianness: Endianness,
+    /// Whether the length counts the header up to and including the length field.
+    #[serde(default)]
+    pub includes_header: bool,
+}
+
+/// How frames are delimited inside the notification stream.
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Framing {
+    /// Every notification is one frame.
+    #[default]
+    Notification,
+    LengthPrefixed(LengthPrefix),
+    /// Frames end with the given byte sequence.
+    Delimiter(Vec<u8>),
+    Slip,
+    Cobs,
+}
+
+#[derive(Default)]
+struct Reassembler {
+    buffer: Vec<u8>,
+}
+
+impl Reassembler {
+    fn push(&mut self, framing: &Framing, data: &[u8]) -> Vec<Frame> {
+        if let Framing::Notification = framing {
+            return vec![Frame {
+                raw: data.to_vec(),
+                payload: data.to_vec(),
+            }];
+        }
+
+        self.buffer.extend_from_slice(data);
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next_frame(framing) {
+            frames.push(frame);
+        }
+
+        if self.buffer.len() > MAX_FRAME_LEN {
+            error!(
+                "Dropping {} bytes without frame boundary",
+                self.buffer.len()
+            );
+            self.buffer.clear();
+        }
+        frames
+    }
+
+    fn next_frame(&mut self, framing: &Framing) -> Option<Frame> {
+        loop {
+            let (end, payload) = match framing {
+                Framing::Notification => return None,
+                Framing::LengthPrefixed(prefix) => {
+                    if prefix.width == 0 {
+                        // Every frame would be empty and nothing consumed.
+                        return None;
+                    }
+                    let header = prefix.offset + prefix.width;
+                    let len = prefix
+                        .endianness
+                        .read(self.buffer.get(prefix.offset..header)?)
+                        as usize;
+                    let end = if prefix.includes_header {
+                        len
+                    } else {
+                        header + len
+                    };
+                    if end < header {
+                        // Length can't be right, resync one byte later.
+                        self.buffer.drain(..1);
+                        continue;
+                    }
+                    if self.buffer.len() < end {
+                        return None;
+                    }
+                    (end, Some(self.buffer[..end].to_vec()))
+                }
+                Framing::Delimiter(delimiter) => {
+                    if delimiter.is_empty() {
+                        return None;
+                    }
+                    let pos = self
+                        .buffer
+                        .windows(delimiter.len())
+                        .position(|w| w == delimiter.as_slice())?;
+                    (pos + delimiter.len(), Some(self.buffer[..pos].to_vec()))
+                }
+                Framing::Slip => {
+                    let pos = self.buffer.iter().position(|b| *b == SLIP_END)?;
+                    (pos + 1, slip_decode(&self.buffer[..pos]))
+                }
+                Framing::Cobs => {
+                    let pos = self.buffer.iter().position(|b| *b == 0)?;
+                    (pos + 1, cobs_decode(&self.buffer[..pos]))
+                }
+            };
+
+            let raw = self.buffer.drain(..end).collect::<Vec<u8>>();
+            match payload {
+                Some(payload) if !payload.is_empty() => return Some(Frame { raw, payload }),
+                Some(_) => continue,
+                None => {
+                    error!("Dropping malformed frame: {:?}", raw);
+                    continue;
+                }
+            }
+        }
+    }
+}
+
+fn slip_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match *byte {
+            SLIP_ESC => match bytes.next() {
+                Some(&SLIP_ESC_END) => out.push(SLIP_END),
+                Some(&SLIP_ESC_ESC) => out.push(SLIP_ESC),
+                _ => return None,
+            },
+            byte => out.push(byte),
+        }
+    }
+    Some(out)
+}
+
+fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 {
+            return None;
+        }
+        let end = i + code;
+        out.extend_from_slice(data.get(i + 1..end)?);
+        i = end;
+        if code < 0xFF && i < data.len() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+/// Per-device frame reassembly for the notification path.
+#[derive(Default)]
+pub struct Reassemblers {
+    framing: Framing,
+    /// Framing of devices that don't use the default one.
+    overrides: HashMap<String, Framing>,
+    /// Framing of each profile that has one, by profile name.
+    profiles: HashMap<String, Framing>,
+    /// Profile each connected device uses.
+    device_profiles: HashMap<String, String>,
+    /// Partial frames by device id and channel tag, since every
+    /// characteristic is a stream of its own.
+    devices: HashMap<(String, u8), Reassembler>,
+}
+
+impl Reassemblers {
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+        self.devices.clear();
+    }
+
+    pub fn set_overrides(&mut self, overrides: HashMap<String, Framing>) {
+        self.overrides = overrides;
+        self.devices.clear();
+    }
+
+    pub fn set_profiles(&mut self, profiles: &[Profile]) {
+        self.profiles = profiles
+            .iter()
+            .filter_map(|profile| Some((profile.name.clone(), profile.framing.clone()?)))
+            .collect();
+        self.devices.clear();
+    }
+
+    /// Frames the device's data as its profile says, unless overridden.
+    pub fn set_device_profile(&mut self, id: &str, profile: Option<String>) {
+        match profile {
+            Some(profile) => self.device_profiles.insert(id.to_string(), profile),
+            None => self.device_profiles.remove(id),
+        };
+        self.reset(id);
+    }
+
+    pub fn reset(&mut self, id: &str) {
+        self.devices.retain(|(device, _), _| device != id);
+    }
+
+    /// Frames data of the device's characteristic `tag`.
+    pub fn push(&mut self, id: &str, tag: u8, data: &[u8]) -> Vec<Frame> {
+        let framing = self
+            .overrides
+            .get(id)
+            .or_else(|| {
+                let profile = self.device_profiles.get(id)?;
+                self.profiles.get(profile)
+            })
+            .unwrap_or(&self.framing);
+        self.devices
+            .entry((id.to_string(), tag))
+            .or_default()
+            .push(framing, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::PRIMARY_TAG;
+
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+
+    fn length_prefixed(width: usize) -> Framing {
+        Framing::LengthPrefixed(LengthPrefix {
+            offset: 0,
+            width,
+            endianness: Endianness::Little,
+            includes_header: false,
+        })
+    }
+
+    fn payloads(frames: Vec<Frame>) -> Vec<Vec<u8>> {
+        frames.into_iter().map(|frame| frame.payload).collect()
+    }
+
+    fn framed(framing: Framing, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut reassemblers = Reassemblers::default();
+        reassemblers.set_framing(framing);
+        payloads(reassemblers.push(DEVICE, PRIMARY_TAG, data))
+    }
+
+    #[test]
+    fn frame_split_across_notifications_is_reassembled() {
+        let mut reassemblers = Reassemblers::default();
+        reassemblers.set_framing(length_prefixed(1));
+
+        assert!(reassemblers.push(DEVICE, PRIMARY_TAG, &[3, 1]).is_empty());
+        assert_eq!(
+            payloads(reassemblers.push(DEVICE, PRIMARY_TAG, &[2, 3, 2, 4])),
+            vec![vec![3, 1, 2, 3]]
+        );
+        assert_eq!(
+            payloads(reassemblers.push(DEVICE, PRIMARY_TAG, &[5])),
+            vec![vec![2, 4, 5]]
+        );
+    }
+
+    #[test]
+    fn several_frames_in_one_notification_are_split() {
+        assert_eq!(
+            framed(length_prefixed(1), &[1, 7, 2, 8, 9, 0, 1, 6]),
+            vec![vec![1, 7], vec![2, 8, 9], vec![0], vec![1, 6]]
+        );
+        assert_eq!(
+            framed(Framing::Slip, &[1, SLIP_END, 2, 3, SLIP_END]),
+            vec![vec![1], vec![2, 3]]
+        );
+    }
+
+    #[test]
+    fn length_may_count_the_header() {
+        let framing = Framing::LengthPrefixed(LengthPrefix {
+            offset: 1,
+            width: 2,
+            endianness: Endianness::Big,
+            includes_header: true,
+        });
+        assert_eq!(
+            framed(framing, &[0xAA, 0, 4, 1, 0xAA, 0, 3]),
+            vec![vec![0xAA, 0, 4, 1], vec![0xAA, 0, 3]]
+        );
+    }
+
+    #[test]
+    fn zero_width_length_prefix_yields_no_frames() {
+        assert!(framed(length_prefixed(0), &[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn delimiter_ends_frames_across_notifications() {
+        let mut reassemblers = Reassemblers::default();
+        reassemblers.set_framing(Framing::Delimiter(b"\r\n".to_vec()));
+
+        assert_eq!(
+            payloads(reassemblers.push(DEVICE, PRIMARY_TAG, b"ab\r\ncd\r")),
+            vec![b"ab".to_vec()]
+        );
+        // The delimiter itself is split, and empty frames are skipped.
+        assert_eq!(
+            payloads(reassemblers.push(DEVICE, PRIMARY_TAG, b"\n\r\nef\r\n")),
+            vec![b"cd".to_vec(), b"ef".to_vec()]
+        );
+    }
+
+    #[test]
+    fn slip_escapes_are_decoded() {
+        let frames = {
+            let mut reassemblers = Reassemblers::default();
+            reassemblers.set_framing(Framing::Slip);
+            reassemblers.push(
+                DEVICE,
+                PRIMARY_TAG,
+                &[
+                    1,
+                    SLIP_ESC,
+                    SLIP_ESC_END,
+                    SLIP_ESC,
+                    SLIP_ESC_ESC,
+                    2,
+                    SLIP_END,
+                ],
+            )
+        };
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].payload, vec![1, SLIP_END, SLIP_ESC, 2]);
+        assert_eq!(frames[0].raw.len(), 7);
+    }
+
+    #[test]
+    fn bad_slip_escape_drops_only_that_frame() {
+        assert_eq!(
+            framed(Framing::Slip, &[1, SLIP_ESC, 3, SLIP_END, 4, SLIP_END]),
+            vec![vec![4]]
+        );
+        assert_eq!(
+            framed(Framing::Slip, &[1, SLIP_ESC, SLIP_END, 5, SLIP_END]),
+            vec![vec![5]]
+        );
+    }
+
+    #[test]
+    fn cobs_restores_zeros() {
+        assert_eq!(
+            framed(Framing::Cobs, &[3, 1, 2, 2, 3, 0, 1, 1, 0]),
+            vec![vec![1, 2, 0, 3], vec![0]]
+        );
+    }
+
+    #[test]
+    fn cobs_full_block_adds_no_zero() {
+        let data: Vec<u8> = (1..=254).collect();
+        let mut encoded = vec![0xFF];
+        encoded.extend_from_slice(&data);
+        encoded.extend_from_slice(&[2, 7, 0]);
+
+        let mut expected = data;
+        expected.push(7);
+        assert_eq!(framed(Framing::Cobs, &encoded), vec![expected]);
+    }
+
+    #[test]
+    fn malformed_cobs_drops_only_that_frame() {
+        // The code runs past the end of the frame.
+        assert_eq!(framed(Framing::Cobs, &[5, 1, 2, 0, 2, 9, 0]), vec![vec![9]]);
+    }
+
+    #[test]
+    fn characteristics_are_reassembled_separately() {
+        let mut reassemblers = Reassemblers::default();
+        reassemblers.set_framing(Framing::Slip);
+
+        assert!(reassemblers.push(DEVICE, PRIMARY_TAG, &[1]).is_empty());
+        assert_eq!(
+            payloads(reassemblers.push(DEVICE, 1, &[2, SLIP_END])),
+            vec![vec![2]]
+        );
+        assert_eq!(
+            payloads(reassemblers.push(DEVICE, PRIMARY_TAG, &[3, SLIP_END])),
+            vec![vec![1, 3]]
+        );
+    }
+}
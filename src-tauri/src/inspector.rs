@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Reads up to four bytes as an unsigned integer.
+    pub fn read(self, bytes: &[u8]) -> u32 {
+        match self {
+            Endianness::Little => bytes
+                .iter()
+                .rev()
+                .fold(0u32, |acc, b| (acc << 8) | *b as u32),
+            Endianness::Big => bytes.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32),
+        }
+    }
+}
+
+/// Location of the rolling frame counter inside a notification.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CounterConfig {
+    /// Byte offset of the counter from the start of the frame.
+    pub offset: usize,
+    /// Counter width in bytes (1, 2 or 4).
+    pub width: usize,
+    #[serde(default)]
+    pub endianness: Endianness,
+    /// Bytes written to the serial stream in front of the first frame after a gap.
+    #[serde(default)]
+    pub gap_marker: Option<Vec<u8>>,
+}
+
+impl CounterConfig {
+    fn read(&self, frame: &[u8]) -> Option<u32> {
+        let bytes = frame.get(self.offset..self.offset + self.width)?;
+        Some(self.endianness.read(bytes))
+    }
+
+    fn modulus(&self) -> u64 {
+        1u64 << (8 * self.width.min(4))
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct FrameStats {
+    pub received: u64,
+    pub missing: u64,
+    pub out_of_order: u64,
+}
+
+/// Emitted whenever a device's counter does not follow the previous frame.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FrameLoss {
+    pub id: String,
+    pub expected: u32,
+    pub actual: u32,
+    /// Frames skipped by this gap, zero when the frame arrived out of order.
+    pub missing: u32,
+    pub stats: FrameStats,
+}
+
+#[derive(Default)]
+struct FrameInspector {
+    /// Last counter of each channel tag; every characteristic counts on its own.
+    last: HashMap<u8, u32>,
+    stats: FrameStats,
+}
+
+/// Per-device sequence counter tracking for the notification path.
+#[derive(Default)]
+pub struct FrameInspectors {
+    config: Option<CounterConfig>,
+    devices: HashMap<String, FrameInspector>,
+}
+
+impl FrameInspectors {
+    pub fn set_config(&mut self, config: Option<CounterConfig>) {
+        self.config = config;
+        self.devices.clear();
+    }
+
+    pub fn reset(&mut self, id: &str) {
+        self.devices.remove(id);
+    }
+
+    pub fn stats(&self) -> HashMap<String, FrameStats> {
+        self.devices
+            .iter()
+            .map(|(id, inspector)| (id.clone(), inspector.stats.clone()))
+            .collect()
+    }
+
+    pub fn gap_marker(&self) -> Option<&[u8]> {
+        self.config.as_ref()?.gap_marker.as_deref()
+    }
+
+    /// Checks the counter of a frame of the device's characteristic `tag`.
+    pub fn inspect(&mut self, id: &str, tag: u8, frame: &[u8]) -> Option<FrameLoss> {
+        let config = self.config.as_ref()?;
+        let actual = config.read(frame)?;
+        let modulus = config.modulus();
+
+        let inspector = self.devices.entry(id.to_string()).or_default();
+        inspector.stats.received += 1;
+
+        let last = match inspector.last.get(&tag) {
+            Some(last) => *last,
+            None => {
+                inspector.last.insert(tag, actual);
+                return None;
+            }
+        };
+
+        let expected = ((last as u64 + 1) % modulus) as u32;
+        if actual == expected {
+            inspector.last.insert(tag, actual);
+            return None;
+        }
+
+        let distance = (actual as u64 + modulus - expected as u64) % modulus;
+        let missing = if distance < modulus / 2 {
+            inspector.stats.missing += distance;
+            inspector.last.insert(tag, actual);
+            distance as u32
+        } else {
+            inspector.stats.out_of_order += 1;
+            0
+        };
+
+        Some(FrameLoss {
+            id: id.to_string(),
+            expected,
+            actual,
+            missing,
+            stats: inspector.stats.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::PRIMARY_TAG;
+
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+
+    fn inspectors(width: usize, endianness: Endianness) -> FrameInspectors {
+        let mut inspectors = FrameInspectors::default();
+        inspectors.set_config(Some(CounterConfig {
+            offset: 1,
+            width,
+            endianness,
+            gap_marker: Some(vec![0xEE]),
+        }));
+        inspectors
+    }
+
+    /// A frame with a sync byte in front of the counter.
+    fn frame(counter: u32, width: usize, endianness: Endianness) -> Vec<u8> {
+        let bytes = match endianness {
+            Endianness::Little => counter.to_le_bytes()[..width].to_vec(),
+            Endianness::Big => counter.to_be_bytes()[4 - width..].to_vec(),
+        };
+        [vec![0xAA], bytes].concat()
+    }
+
+    #[test]
+    fn counters_wrap_at_their_width() {
+        for (width, endianness) in [
+            (1, Endianness::Little),
+            (2, Endianness::Big),
+            (4, Endianness::Little),
+            (4, Endianness::Big),
+        ] {
+            let mut inspectors = inspectors(width, endianness);
+            let max = ((1u64 << (8 * width)) - 1) as u32;
+            for counter in [max - 1, max, 0, 1] {
+                let loss =
+                    inspectors.inspect(DEVICE, PRIMARY_TAG, &frame(counter, width, endianness));
+                assert!(loss.is_none(), "width {} at {}", width, counter);
+            }
+            let stats = &inspectors.stats()[DEVICE];
+            assert_eq!((stats.received, stats.missing), (4, 0));
+        }
+    }
+
+    #[test]
+    fn gap_counts_missing_frames() {
+        let mut inspectors = inspectors(1, Endianness::Little);
+        assert!(inspectors
+            .inspect(DEVICE, PRIMARY_TAG, &[0xAA, 254])
+            .is_none());
+
+        // 255 and 0 never arrived.
+        let loss = inspectors.inspect(DEVICE, PRIMARY_TAG, &[0xAA, 1]).unwrap();
+        assert_eq!((loss.expected, loss.actual, loss.missing), (255, 1, 2));
+        assert_eq!(loss.stats.missing, 2);
+        assert_eq!(inspectors.gap_marker(), Some(&[0xEE][..]));
+
+        assert!(inspectors
+            .inspect(DEVICE, PRIMARY_TAG, &[0xAA, 2])
+            .is_none());
+    }
+
+    #[test]
+    fn late_frame_is_out_of_order() {
+        let mut inspectors = inspectors(2, Endianness::Little);
+        inspectors.inspect(DEVICE, PRIMARY_TAG, &frame(10, 2, Endianness::Little));
+        inspectors.inspect(DEVICE, PRIMARY_TAG, &frame(12, 2, Endianness::Little));
+
+        // More than half the range ahead means it's behind.
+        let loss = inspectors
+            .inspect(DEVICE, PRIMARY_TAG, &frame(11, 2, Endianness::Little))
+            .unwrap();
+        assert_eq!((loss.expected, loss.actual, loss.missing), (13, 11, 0));
+        assert_eq!(loss.stats.out_of_order, 1);
+        assert_eq!(loss.stats.missing, 1);
+
+        // The late frame doesn't move the counter back.
+        assert!(inspectors
+            .inspect(DEVICE, PRIMARY_TAG, &frame(13, 2, Endianness::Little))
+            .is_none());
+    }
+
+    #[test]
+    fn each_tag_counts_on_its_own() {
+        let mut inspectors = inspectors(1, Endianness::Little);
+        inspectors.inspect(DEVICE, PRIMARY_TAG, &[0xAA, 5]);
+        inspectors.inspect(DEVICE, 1, &[0xAA, 100]);
+        assert!(inspectors
+            .inspect(DEVICE, PRIMARY_TAG, &[0xAA, 6])
+            .is_none());
+        assert!(inspectors.inspect(DEVICE, 1, &[0xAA, 101]).is_none());
+
+        let loss = inspectors.inspect(DEVICE, 1, &[0xAA, 103]).unwrap();
+        assert_eq!(loss.missing, 1);
+        // Stats are the device's, across tags.
+        assert_eq!((loss.stats.received, loss.stats.missing), (5, 1));
+    }
+
+    #[test]
+    fn frame_without_a_counter_is_ignored() {
+        let mut inspectors = inspectors(2, Endianness::Little);
+        assert!(inspectors
+            .inspect(DEVICE, PRIMARY_TAG, &[0xAA, 1])
+            .is_none());
+        assert!(inspectors.stats().is_empty());
+    }
+}
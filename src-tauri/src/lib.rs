@@ -0,0 +1,20 @@
+pub mod advertisement;
+pub mod app;
+pub mod ble;
+pub mod bus;
+pub mod channel;
+pub mod crc;
+pub mod decoder;
+pub mod device_info;
+pub mod framing;
+pub mod inspector;
+pub mod metrics;
+pub mod poll;
+pub mod port;
+pub mod profile;
+pub mod rest;
+pub mod rpc;
+pub mod session;
+pub mod settings;
+pub mod sink;
+pub mod write_queue;
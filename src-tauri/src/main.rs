@@ -1,25 +1,35 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use app::AppStateType;
-use ble::Device;
+use ble2serial::advertisement::AdvertisementData;
+use ble2serial::app::{self, AppStateType};
+use ble2serial::ble::{Device, ADDRESS_SCAN_TIMEOUT};
+use ble2serial::crc::{BadFramePolicy, CrcConfig, CrcStats};
+use ble2serial::{rest, rpc};
+use ble2serial::decoder::DecoderConfig;
+use ble2serial::framing::Framing;
+use ble2serial::inspector::{CounterConfig, FrameStats};
+use ble2serial::profile::Profile;
+use ble2serial::settings::{FieldError, KnownDevice, Settings};
+use ble2serial::sink::{SinkConfig, SinkInfo};
+use ble2serial::write_queue::WriteStats;
 use log::error;
+use std::collections::HashMap;
 use std::{error::Error, path::PathBuf, process::Output, sync::Arc};
-use tauri::Manager;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tauri::{Manager, RunEvent};
 use tauri::State;
 use tauri_plugin_log::LogTarget;
 use tokio::sync::Mutex;
 use log::debug;
 
-use crate::ble::BleEvent;
-mod app;
-mod ble;
-mod port;
+use ble2serial::ble::BleEvent;
+use ble2serial::bus::{Received, Topic};
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
 async fn start_scan(state: State<'_, AppStateType>) -> Result<(), String> {
-    let mut state = state.lock().await;
     if let Err(err) = state.ble_manager.start_scan().await {
         println!("Error starting scan: {}", err);
         return Err(err.to_string());
@@ -30,8 +40,6 @@ async fn start_scan(state: State<'_, AppStateType>) -> Result<(), String> {
 #[tauri::command]
 async fn stop_scan(state: State<'_, AppStateType>) -> Result<(), String> {
     state
-        .lock()
-        .await
         .ble_manager
         .stop_scan()
         .await
@@ -42,8 +50,6 @@ async fn stop_scan(state: State<'_, AppStateType>) -> Result<(), String> {
 #[tauri::command]
 async fn connect(state: State<'_, AppStateType>, id: String) -> Result<(), String> {
     state
-        .lock()
-        .await
         .ble_manager
         .connect_device(id)
         .await
@@ -51,11 +57,24 @@ async fn connect(state: State<'_, AppStateType>, id: String) -> Result<(), Strin
     Ok(())
 }
 
+/// Connects to a device that hasn't been discovered, returning its id.
 #[tauri::command]
-async fn disconnect(state: State<'_, AppStateType>, id: String) -> Result<(), String> {
+async fn connect_address(
+    state: State<'_, AppStateType>,
+    address: String,
+    timeout_ms: Option<u64>,
+) -> Result<String, String> {
+    let timeout = timeout_ms.map_or(ADDRESS_SCAN_TIMEOUT, Duration::from_millis);
     state
-        .lock()
+        .ble_manager
+        .connect_address(&address, timeout)
         .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn disconnect(state: State<'_, AppStateType>, id: String) -> Result<(), String> {
+    state
         .ble_manager
         .disconnect_device(id)
         .await
@@ -63,16 +82,42 @@ async fn disconnect(state: State<'_, AppStateType>, id: String) -> Result<(), St
     Ok(())
 }
 
+#[tauri::command]
+async fn write(state: State<'_, AppStateType>, id: String, data: Vec<u8>) -> Result<(), String> {
+    state
+        .ble_manager
+        .write(&id, data)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_write_stats(
+    state: State<'_, AppStateType>,
+) -> Result<HashMap<String, WriteStats>, String> {
+    Ok(state.ble_manager.write_stats().await)
+}
+
 #[tauri::command]
 async fn get_other_port(state: State<'_, AppStateType>) -> Result<Option<String>, String> {
-    let state = state.lock().await;
-    let port = state.other_port.clone();
+    let port = state.other_port.lock().await.clone();
     Ok(port)
 }
 
+#[tauri::command]
+async fn get_advertisement(
+    state: State<'_, AppStateType>,
+    id: String,
+) -> Result<AdvertisementData, String> {
+    state
+        .ble_manager
+        .get_advertisement(&id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn get_devices(state: State<'_, AppStateType>) -> Result<Vec<Device>, String> {
-    let state = state.lock().await;
     let devices = state
         .ble_manager
         .get_devices()
@@ -81,6 +126,175 @@ async fn get_devices(state: State<'_, AppStateType>) -> Result<Vec<Device>, Stri
     Ok(devices)
 }
 
+fn join_errors(errors: Vec<FieldError>) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[tauri::command]
+async fn get_settings(state: State<'_, AppStateType>) -> Result<Settings, String> {
+    Ok(state.settings().await)
+}
+
+#[tauri::command]
+async fn update_settings(
+    state: State<'_, AppStateType>,
+    settings: Settings,
+) -> Result<Settings, Vec<FieldError>> {
+    state.update_settings(settings).await?;
+    Ok(state.settings().await)
+}
+
+#[tauri::command]
+async fn set_counter_config(
+    state: State<'_, AppStateType>,
+    config: Option<CounterConfig>,
+) -> Result<(), String> {
+    state
+        .update_settings_with(|settings| {
+            settings.counter = config;
+            Ok(())
+        })
+        .await
+        .map_err(join_errors)
+}
+
+#[tauri::command]
+async fn get_profiles(state: State<'_, AppStateType>) -> Result<Vec<Profile>, String> {
+    Ok(state.settings().await.ble.registry().list().to_vec())
+}
+
+#[tauri::command]
+async fn get_known_devices(state: State<'_, AppStateType>) -> Result<Vec<KnownDevice>, String> {
+    Ok(state.known_devices().await)
+}
+
+#[tauri::command]
+async fn set_known_device(
+    state: State<'_, AppStateType>,
+    device: KnownDevice,
+) -> Result<(), Vec<FieldError>> {
+    state.set_known_device(device).await
+}
+
+#[tauri::command]
+async fn set_auto_connect(
+    state: State<'_, AppStateType>,
+    address: String,
+    enabled: bool,
+) -> Result<(), String> {
+    state
+        .set_auto_connect(&address, enabled)
+        .await
+        .map_err(join_errors)
+}
+
+#[tauri::command]
+async fn forget_device(state: State<'_, AppStateType>, address: String) -> Result<bool, String> {
+    state.forget_device(&address).await.map_err(join_errors)
+}
+
+#[tauri::command]
+async fn get_frame_stats(
+    state: State<'_, AppStateType>,
+) -> Result<HashMap<String, FrameStats>, String> {
+    let stats = state.inspectors.lock().await.stats();
+    Ok(stats)
+}
+
+#[tauri::command]
+async fn set_framing(state: State<'_, AppStateType>, framing: Framing) -> Result<(), String> {
+    state
+        .update_settings_with(|settings| {
+            settings.framing = framing;
+            Ok(())
+        })
+        .await
+        .map_err(join_errors)
+}
+
+#[tauri::command]
+async fn set_crc_config(
+    state: State<'_, AppStateType>,
+    config: Option<CrcConfig>,
+) -> Result<(), String> {
+    state
+        .update_settings_with(|settings| {
+            settings.crc = config;
+            Ok(())
+        })
+        .await
+        .map_err(join_errors)
+}
+
+#[tauri::command]
+async fn set_crc_policy(
+    state: State<'_, AppStateType>,
+    id: String,
+    policy: Option<BadFramePolicy>,
+) -> Result<(), String> {
+    state
+        .set_crc_policy(&id, policy)
+        .await
+        .map_err(join_errors)
+}
+
+#[tauri::command]
+async fn get_crc_stats(
+    state: State<'_, AppStateType>,
+) -> Result<HashMap<String, CrcStats>, String> {
+    let stats = state.crc_checkers.lock().await.stats();
+    Ok(stats)
+}
+
+#[tauri::command]
+async fn set_decoder(
+    state: State<'_, AppStateType>,
+    config: Option<DecoderConfig>,
+) -> Result<(), String> {
+    state
+        .update_settings_with(|settings| {
+            settings.decoder = config;
+            Ok(())
+        })
+        .await
+        .map_err(join_errors)
+}
+
+#[tauri::command]
+async fn get_decoded_port(state: State<'_, AppStateType>) -> Result<Option<String>, String> {
+    let port = state.decoded_port.lock().await.clone();
+    Ok(port)
+}
+
+#[tauri::command]
+async fn add_sink(
+    state: State<'_, AppStateType>,
+    name: String,
+    device: Option<String>,
+    config: SinkConfig,
+) -> Result<SinkInfo, String> {
+    state
+        .add_sink(name, device, config)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_sink(state: State<'_, AppStateType>, name: String) -> Result<bool, String> {
+    let removed = state.remove_sink(&name).await;
+    Ok(removed)
+}
+
+#[tauri::command]
+async fn get_sinks(state: State<'_, AppStateType>) -> Result<Vec<SinkInfo>, String> {
+    let sinks = state.sinks.lock().await.list();
+    Ok(sinks)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     
@@ -89,8 +303,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
     tauri::async_runtime::set(tokio::runtime::Handle::current());
     
 
-    let (ui_tx, mut ui_rx) = tokio::sync::mpsc::channel::<BleEvent>(100);
     let app_state = app::AppState::new().await;
+    let shutdown_state = app_state.clone();
+    let shutting_down = Arc::new(AtomicBool::new(false));
+
     tauri::Builder::default()
         .plugin(
             tauri_plugin_log::Builder::default()
@@ -102,9 +318,31 @@ async fn main() -> Result<(), Box<dyn Error>> {
             start_scan,
             stop_scan,
             connect,
+            connect_address,
             disconnect,
             get_other_port,
-            get_devices
+            get_devices,
+            get_advertisement,
+            get_settings,
+            update_settings,
+            set_counter_config,
+            get_profiles,
+            write,
+            get_write_stats,
+            get_known_devices,
+            set_known_device,
+            set_auto_connect,
+            forget_device,
+            get_frame_stats,
+            set_framing,
+            set_crc_config,
+            set_crc_policy,
+            get_crc_stats,
+            set_decoder,
+            get_decoded_port,
+            add_sink,
+            remove_sink,
+            get_sinks
         ])
         .setup(move |app| {
             let cloned = app_state.clone();
@@ -118,28 +356,66 @@ async fn main() -> Result<(), Box<dyn Error>> {
             let window = app.get_window("main").unwrap();
 
             tauri::async_runtime::spawn(async move {
-                {
-                    let mut state = cloned.lock().await;
-                    state.set_resource_path(resource_path).await;
-                    state.start_loop(ui_tx.clone()).await;
-                }
+                cloned.set_resource_path(resource_path).await;
+                let mut events = cloned.bus.subscribe(&[
+                    Topic::Lifecycle,
+                    Topic::Errors,
+                    Topic::Advertisements,
+                ]);
+                cloned.start_loop().await;
+                let settings = cloned.settings().await;
+                rpc::spawn_server(cloned.clone(), &settings.rpc);
+                rest::spawn_server(cloned.clone(), &settings.http);
 
                 debug!("Starting UI loop..");
 
-                while let Some(event) = ui_rx.recv().await {
-                    match event {
-                        BleEvent::DeviceDiscovered(devices) => {
-                            if let Err(err) = window.emit("devices", devices) {
+                while let Some(received) = events.recv().await {
+                    let event = match received {
+                        Received::Event(event) => event,
+                        Received::Lagged { topic, missed } => {
+                            error!("UI missed {} {:?} events", missed, topic);
+                            continue;
+                        }
+                    };
+
+                    match &*event {
+                        BleEvent::DeviceDiscovered(device) => {
+                            if let Err(err) = window.emit("device-added", device) {
+                                error!("Error sending devices to UI: {}", err);
+                            };
+                        }
+                        BleEvent::DeviceUpdated(device) => {
+                            if let Err(err) = window.emit("device-updated", device) {
+                                error!("Error sending devices to UI: {}", err);
+                            };
+                        }
+                        BleEvent::DeviceAdvertisement(advertisement) => {
+                            if let Err(err) = window.emit("advertisement", advertisement) {
+                                error!("Error sending advertisement to UI: {}", err);
+                            };
+                        }
+                        BleEvent::DeviceLost(id) => {
+                            if let Err(err) = window.emit("device-lost", id) {
                                 error!("Error sending devices to UI: {}", err);
                             };
                         }
+                        BleEvent::BatteryLevel(level) => {
+                            if let Err(err) = window.emit("battery-level", level) {
+                                error!("Error sending battery level to UI: {}", err);
+                            };
+                        }
+                        BleEvent::BatteryLow(level) => {
+                            if let Err(err) = window.emit("battery-low", level) {
+                                error!("Error sending battery level to UI: {}", err);
+                            };
+                        }
                         BleEvent::DeviceConnected(device) => {
                             if let Err(err) = window.emit("connected", device) {
                                 error!("Error sending devices to UI: {}", err);
                             };
                         }
-                        BleEvent::DeviceDisconnected => {
-                            if let Err(err) = window.emit("disconnected", ()) {
+                        BleEvent::DeviceDisconnected(id) => {
+                            if let Err(err) = window.emit("disconnected", id) {
                                 error!("Error sending devices to UI: {}", err);
                             };
                         }
@@ -148,6 +424,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 error!("Error sending devices to UI: {}", err);
                             };
                         }
+                        BleEvent::FrameLoss(loss) => {
+                            if let Err(err) = window.emit("frame-loss", loss) {
+                                error!("Error sending frame loss to UI: {}", err);
+                            };
+                        }
+                        BleEvent::CrcError(crc_error) => {
+                            if let Err(err) = window.emit("crc-error", crc_error) {
+                                error!("Error sending CRC error to UI: {}", err);
+                            };
+                        }
                         _ => {}
                     }
                 }
@@ -155,8 +441,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             Ok(()) // This will print 'Guten Tag!' to the terminal
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(move |handle, event| {
+            // Keep the process alive until devices are disconnected and ports flushed.
+            if let RunEvent::ExitRequested { api, .. } = event {
+                if shutting_down.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                api.prevent_exit();
+                let handle = handle.clone();
+                let state = shutdown_state.clone();
+                tauri::async_runtime::spawn(async move {
+                    state.shutdown().await;
+                    handle.exit(0);
+                });
+            }
+        });
 
     Ok(())
 }
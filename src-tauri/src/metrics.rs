@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+use crate::app::AppStateType;
+use crate::ble::DeviceCounters;
+
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// Writes one metric family; `samples` maps a label value to a sample.
+fn family(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    label: &str,
+    samples: &BTreeMap<String, u64>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (value, sample) in samples {
+        let _ = writeln!(
+            out,
+            "{}{{{}=\"{}\"}} {}",
+            name,
+            label,
+            escape(value),
+            sample
+        );
+    }
+}
+
+fn single(out: &mut String, name: &str, kind: &str, help: &str, sample: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, sample);
+}
+
+/// Renders every bridge counter in the Prometheus text format.
+pub async fn render(state: &AppStateType) -> String {
+    let devices = state.ble_manager.counters().await;
+    let event_loop_errors = state.ble_manager.event_loop_errors();
+    let missed_events = state.missed_events.load(Ordering::Relaxed);
+    let bridged = state.bridge_counters.lock().await.clone();
+    let (sinks, port_errors) = {
+        let sinks = state.sinks.lock().await;
+        (sinks.list(), sinks.port_errors())
+    };
+
+    let per_device = |load: &dyn Fn(&DeviceCounters) -> u64| {
+        devices
+            .iter()
+            .map(|(id, counters)| (id.clone(), load(counters)))
+            .collect::<BTreeMap<_, _>>()
+    };
+
+    let mut out = String::new();
+    family(
+        &mut out,
+        "ble2serial_device_connected",
+        "gauge",
+        "Whether the device is connected.",
+        "device",
+        &per_device(&|c| c.connected.load(Ordering::Relaxed) as u64),
+    );
+    family(
+        &mut out,
+        "ble2serial_device_connections_total",
+        "counter",
+        "Connections made to the device.",
+        "device",
+        &per_device(&|c| c.connections.load(Ordering::Relaxed)),
+    );
+    family(
+        &mut out,
+        "ble2serial_device_reconnects_total",
+        "counter",
+        "Connections made to the device after the first one.",
+        "device",
+        &per_device(&|c| c.connections.load(Ordering::Relaxed).saturating_sub(1)),
+    );
+    family(
+        &mut out,
+        "ble2serial_notifications_total",
+        "counter",
+        "Notifications received from the device.",
+        "device",
+        &per_device(&|c| c.notifications.load(Ordering::Relaxed)),
+    );
+    family(
+        &mut out,
+        "ble2serial_notification_bytes_total",
+        "counter",
+        "Notification payload bytes received from the device.",
+        "device",
+        &per_device(&|c| c.notification_bytes.load(Ordering::Relaxed)),
+    );
+    family(
+        &mut out,
+        "ble2serial_device_errors_total",
+        "counter",
+        "Event loop failures while setting up the device.",
+        "device",
+        &per_device(&|c| c.errors.load(Ordering::Relaxed)),
+    );
+    family(
+        &mut out,
+        "ble2serial_late_polls_total",
+        "counter",
+        "Polls that started later than their jitter tolerance.",
+        "device",
+        &per_device(&|c| c.late_polls.load(Ordering::Relaxed)),
+    );
+    family(
+        &mut out,
+        "ble2serial_write_queue_depth",
+        "gauge",
+        "Writes queued or being written to the device.",
+        "device",
+        &per_device(&|c| c.writes.stats().queue_depth),
+    );
+    family(
+        &mut out,
+        "ble2serial_write_failures_total",
+        "counter",
+        "Writes given up on after running out of retries.",
+        "device",
+        &per_device(&|c| c.writes.stats().failures),
+    );
+    family(
+        &mut out,
+        "ble2serial_bridged_frames_total",
+        "counter",
+        "Frames handed to the sinks.",
+        "device",
+        &bridged
+            .iter()
+            .map(|(id, counters)| (id.clone(), counters.frames))
+            .collect(),
+    );
+    family(
+        &mut out,
+        "ble2serial_bridged_bytes_total",
+        "counter",
+        "Bytes, markers included, handed to the sinks.",
+        "device",
+        &bridged
+            .iter()
+            .map(|(id, counters)| (id.clone(), counters.bytes))
+            .collect(),
+    );
+    family(
+        &mut out,
+        "ble2serial_sink_written_total",
+        "counter",
+        "Items written by the sink.",
+        "sink",
+        &sinks
+            .iter()
+            .map(|sink| (sink.name.clone(), sink.written))
+            .collect(),
+    );
+    family(
+        &mut out,
+        "ble2serial_sink_dropped_total",
+        "counter",
+        "Items dropped because the sink fell behind.",
+        "sink",
+        &sinks
+            .iter()
+            .map(|sink| (sink.name.clone(), sink.dropped))
+            .collect(),
+    );
+    family(
+        &mut out,
+        "ble2serial_sink_errors_total",
+        "counter",
+        "Write errors of the sink.",
+        "sink",
+        &sinks
+            .iter()
+            .map(|sink| (sink.name.clone(), sink.errors))
+            .collect(),
+    );
+    family(
+        &mut out,
+        "ble2serial_port_write_errors_total",
+        "counter",
+        "Errors writing the device's data to a serial or com0com port.",
+        "device",
+        &port_errors.into_iter().collect(),
+    );
+    single(
+        &mut out,
+        "ble2serial_event_loop_errors_total",
+        "counter",
+        "BLE event loop failures not tied to a device.",
+        event_loop_errors,
+    );
+    single(
+        &mut out,
+        "ble2serial_bridge_missed_events_total",
+        "counter",
+        "Events the bridge fell too far behind to process.",
+        missed_events,
+    );
+    out
+}
@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use btleplug::api::{Characteristic, Peripheral, ValueNotification};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use log::{debug, error};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// Reads a characteristic periodically, for devices that can't notify.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PollConfig {
+    pub interval_ms: u64,
+    /// How late a read may start before it counts as a late poll. After a
+    /// slow read the reads it held up are skipped, not made up for.
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Forward a value only when it differs from the previous read.
+    #[serde(default)]
+    pub changes_only: bool,
+}
+
+/// Reads every characteristic at its interval. Values come out of the
+/// returned stream as if notified, next to the tasks doing the reads, which
+/// belong to the device's session.
+///
+/// Reads that start later than their jitter tolerance are counted in `late`.
+pub fn spawn<P>(
+    peripheral: &P,
+    polls: Vec<(Characteristic, PollConfig)>,
+    late: Arc<AtomicU64>,
+) -> (UnboundedReceiver<ValueNotification>, Vec<JoinHandle<()>>)
+where
+    P: Peripheral + 'static,
+{
+    let (tx, rx) = mpsc::unbounded();
+    let tasks = polls
+        .into_iter()
+        .map(|(characteristic, config)| {
+            tokio::spawn(run(
+                peripheral.clone(),
+                characteristic,
+                config,
+                tx.clone(),
+                late.clone(),
+            ))
+        })
+        .collect();
+    (rx, tasks)
+}
+
+async fn run<P: Peripheral>(
+    peripheral: P,
+    characteristic: Characteristic,
+    config: PollConfig,
+    tx: UnboundedSender<ValueNotification>,
+    late: Arc<AtomicU64>,
+) {
+    let jitter = Duration::from_millis(config.jitter_ms);
+    let mut ticks = tokio::time::interval(Duration::from_millis(config.interval_ms));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last: Option<Vec<u8>> = None;
+
+    loop {
+        let scheduled = ticks.tick().await;
+        if tx.is_closed() {
+            break;
+        }
+        let delay = scheduled.elapsed();
+        if delay > jitter {
+            late.fetch_add(1, Ordering::Relaxed);
+            debug!(
+                "Polling {} started {} ms late",
+                characteristic.uuid,
+                delay.as_millis()
+            );
+        }
+        let value = match peripheral.read(&characteristic).await {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Error polling {}: {}", characteristic.uuid, err);
+                continue;
+            }
+        };
+        if config.changes_only && last.as_ref() == Some(&value) {
+            continue;
+        }
+        last = Some(value.clone());
+        let notification = ValueNotification {
+            uuid: characteristic.uuid,
+            value,
+        };
+        if tx.unbounded_send(notification).is_err() {
+            break;
+        }
+    }
+    debug!("Stopped polling {}", characteristic.uuid);
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 use std::error::Error;
+#[cfg(windows)]
 use std::os::windows::process::ExitStatusExt;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
 use std::time::Duration;
 use std::{path::PathBuf, process::Output};
 
@@ -10,23 +13,37 @@ use regex::Regex;
 use tokio::process::Command;
 use log::{error, debug};
 
+use crate::settings::PortSettings;
+
 pub struct PortManager {
     resource_path: Option<PathBuf>,
     other_port: Option<String>,
+    settings: PortSettings,
 }
 
 impl PortManager {
-    pub async fn new() -> Self {
+    pub async fn new(settings: PortSettings) -> Self {
         let manager = Self {
             resource_path: None,
 
             other_port: None,
+            settings,
         };
         manager
     }
 
-    pub async fn init(&self) -> (Box<dyn SerialPort>, std::string::String) {
-        let port_info = match Self::check_ports().await {
+    /// Takes effect the next time a pair is opened.
+    pub fn set_settings(&mut self, settings: PortSettings) {
+        self.settings = settings;
+    }
+
+    /// Installs com0com pair `CNCA{index}`/`CNCB{index}` if needed, opens the
+    /// A side and returns it with the COM name of the B side.
+    pub async fn init_pair(
+        &self,
+        index: u32,
+    ) -> Result<(Box<dyn SerialPort>, std::string::String), Box<dyn Error>> {
+        let port_info = match Self::check_ports(index).await {
             Ok(ports) => ports,
             Err(err) => {
                 error!("Error getting available ports: {}", err);
@@ -36,19 +53,24 @@ impl PortManager {
 
         if port_info.is_none() {
             self.preinstall().await;
-            self.remove_ports().await;
-            self.install_ports().await;
+            self.remove_ports(index).await;
+            self.install_ports(index).await;
         }
 
-        let mut port = serialport::new("CNCA0", 115200)
-            .timeout(Duration::from_millis(10))
-            .open()
-            .expect("Failed to open port");
+        let port = self.open_port(&format!("CNCA{}", index))?;
 
-        let other_port = self.get_ports("CNCB0").await;
+        let other_port = self.get_ports(&format!("CNCB{}", index)).await;
 
         debug!("Other port: {}", other_port);
-        (port, other_port)
+        Ok((port, other_port))
+    }
+
+    /// Opens any serial port by name with the configured baud rate.
+    pub fn open_port(&self, name: &str) -> Result<Box<dyn SerialPort>, Box<dyn Error>> {
+        let port = serialport::new(name, self.settings.baud_rate)
+            .timeout(Duration::from_millis(10))
+            .open()?;
+        Ok(port)
     }
 
     async fn preinstall(&self) {
@@ -118,29 +140,42 @@ impl PortManager {
         }
     }
 
-    pub async fn install_ports(&self) {
-        self.run_command(&["install", "0", "PortName=CNCA0", "PortName=COM#"])
+    pub async fn install_ports(&self, index: u32) {
+        let index_arg = index.to_string();
+        let port_name = format!("PortName=CNCA{}", index);
+        let other_port_name = match &self.settings.other_port_name {
+            Some(name) if index == self.settings.pair => format!("PortName={}", name),
+            _ => "PortName=COM#".to_string(),
+        };
+        self.run_command(&["install", &index_arg, &port_name, &other_port_name])
             .await;
-        self.after_install().await;
+        self.after_install(index).await;
     }
 
-    pub async fn remove_ports(&self) -> Output {
-        self.run_command(&["remove", "0"]).await
+    pub async fn remove_ports(&self, index: u32) -> Output {
+        self.run_command(&["remove", &index.to_string()]).await
     }
 
-    async fn after_install(&self) {
-        self.run_command(&["change", "CNCB0", "EmuOverrun=yes"])
+    async fn after_install(&self, index: u32) {
+        self.run_command(&["change", &format!("CNCB{}", index), "EmuOverrun=yes"])
+            .await;
+        self.run_command(&["change", &format!("CNCA{}", index), "EmuBR=yes"])
             .await;
-        self.run_command(&["change", "CNCA0", "EmuBR=yes"]).await;
     }
 
     pub async fn set_resource_path(&mut self, resource_path: PathBuf) {
         self.resource_path = Some(resource_path);
     }
 
-    async fn check_ports() -> Result<Option<SerialPortInfo>, Box<dyn Error>> {
+    async fn check_ports(index: u32) -> Result<Option<SerialPortInfo>, Box<dyn Error>> {
+        let name = format!("CNCA{}", index);
         let ports = serialport::available_ports()?;
-        let port_info = ports.into_iter().find(|p| p.port_name.contains("CNCA0"));
+        // Match whole names so CNCA1 doesn't match CNCA10.
+        let port_info = ports.into_iter().find(|p| {
+            p.port_name
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|token| token == name)
+        });
         Ok(port_info)
     }
 }
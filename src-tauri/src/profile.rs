@@ -0,0 +1,147 @@
+use btleplug::api::Characteristic;
+use uuid::Uuid;
+
+use crate::framing::Framing;
+use crate::poll::PollConfig;
+
+const BIOSIGNAL_SERVICE: Uuid = Uuid::from_u128(0x0000ffa0_0000_1000_8000_00805f9b34fb);
+const BIOSIGNAL_DATA: Uuid = Uuid::from_u128(0x0000ffa1_0000_1000_8000_00805f9b34fb);
+
+const HM10_SERVICE: Uuid = Uuid::from_u128(0x0000ffe0_0000_1000_8000_00805f9b34fb);
+const HM10_DATA: Uuid = Uuid::from_u128(0x0000ffe1_0000_1000_8000_00805f9b34fb);
+
+const NORDIC_UART_SERVICE: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+const NORDIC_UART_RX: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+const NORDIC_UART_TX: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+const MICROCHIP_SERVICE: Uuid = Uuid::from_u128(0x49535343_fe7d_4ae5_8fa9_9fafd205e455);
+const MICROCHIP_TX: Uuid = Uuid::from_u128(0x49535343_1e4d_4bd9_ba61_23c647249616);
+const MICROCHIP_RX: Uuid = Uuid::from_u128(0x49535343_8841_43f4_a8d4_ecbe34729bb3);
+
+/// A BLE serial service: where data arrives, where it is written and how it
+/// is framed.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub service_uuid: Uuid,
+    /// Characteristic the device notifies or indicates data on.
+    pub notify_uuid: Uuid,
+    /// Characteristic written to send data to the device.
+    #[serde(default)]
+    pub write_uuid: Option<Uuid>,
+    /// Framing of the profile's devices; the settings' framing when unset.
+    #[serde(default)]
+    pub framing: Option<Framing>,
+    /// Read the notify characteristic periodically instead of subscribing.
+    #[serde(default)]
+    pub poll: Option<PollConfig>,
+}
+
+impl Profile {
+    fn new(name: &str, service_uuid: Uuid, notify_uuid: Uuid, write_uuid: Uuid) -> Self {
+        Self {
+            name: name.to_string(),
+            service_uuid,
+            notify_uuid,
+            write_uuid: Some(write_uuid),
+            framing: None,
+            poll: None,
+        }
+    }
+
+    /// The profile's notify characteristic among `characteristics`.
+    pub fn notify_characteristic<'a>(
+        &self,
+        characteristics: impl IntoIterator<Item = &'a Characteristic>,
+    ) -> Option<&'a Characteristic> {
+        self.find(characteristics, self.notify_uuid)
+    }
+
+    pub fn write_characteristic<'a>(
+        &self,
+        characteristics: impl IntoIterator<Item = &'a Characteristic>,
+    ) -> Option<&'a Characteristic> {
+        let write_uuid = self.write_uuid?;
+        self.find(characteristics, write_uuid)
+    }
+
+    fn find<'a>(
+        &self,
+        characteristics: impl IntoIterator<Item = &'a Characteristic>,
+        uuid: Uuid,
+    ) -> Option<&'a Characteristic> {
+        characteristics
+            .into_iter()
+            .find(|c| c.service_uuid == self.service_uuid && c.uuid == uuid)
+    }
+}
+
+/// Profiles that ship with the app.
+pub fn builtin() -> Vec<Profile> {
+    vec![
+        Profile {
+            name: "BioSignal".to_string(),
+            service_uuid: BIOSIGNAL_SERVICE,
+            notify_uuid: BIOSIGNAL_DATA,
+            write_uuid: None,
+            framing: None,
+            poll: None,
+        },
+        Profile::new("HM-10", HM10_SERVICE, HM10_DATA, HM10_DATA),
+        Profile::new(
+            "Nordic UART",
+            NORDIC_UART_SERVICE,
+            NORDIC_UART_TX,
+            NORDIC_UART_RX,
+        ),
+        Profile::new(
+            "Microchip Transparent UART",
+            MICROCHIP_SERVICE,
+            MICROCHIP_TX,
+            MICROCHIP_RX,
+        ),
+    ]
+}
+
+/// Built-in and user-defined profiles, looked up by name or detected from a
+/// device's characteristics.
+#[derive(Debug, Clone)]
+pub struct ProfileRegistry {
+    /// User profiles first, so they win over built-in ones.
+    profiles: Vec<Profile>,
+}
+
+impl ProfileRegistry {
+    pub fn new(user: &[Profile]) -> Self {
+        let mut profiles = user.to_vec();
+        profiles.extend(builtin());
+        Self { profiles }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// The first profile whose notify characteristic the device has.
+    pub fn detect<'a>(
+        &self,
+        characteristics: impl Clone + IntoIterator<Item = &'a Characteristic>,
+    ) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| {
+            profile
+                .notify_characteristic(characteristics.clone())
+                .is_some()
+        })
+    }
+
+    /// The first profile with the given service and notify characteristic.
+    pub fn find(&self, service_uuid: Uuid, notify_uuid: Uuid) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| {
+            profile.service_uuid == service_uuid && profile.notify_uuid == notify_uuid
+        })
+    }
+
+    pub fn list(&self) -> &[Profile] {
+        &self.profiles
+    }
+}
@@ -0,0 +1,1207 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put, MethodRouter};
+use axum::{Json, Router};
+use futures::stream::{self, Stream};
+use log::{debug, error};
+use serde_json::{json, Value};
+
+use crate::advertisement::AdvertisementData;
+use crate::app::AppStateType;
+use crate::ble::{Device, ADDRESS_SCAN_TIMEOUT};
+use crate::bus::{Received, Topic};
+use crate::crc::{BadFramePolicy, CrcStats};
+use crate::inspector::FrameStats;
+use crate::metrics;
+use crate::profile::Profile;
+use crate::rpc::{self, Status};
+use crate::settings::{HttpSettings, Settings};
+use crate::sink::SinkInfo;
+use crate::write_queue::WriteStats;
+
+const JSON: &str = "application/json";
+const EVENT_STREAM: &str = "text/event-stream";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+pub struct ApiError {
+    status: StatusCode,
+    body: Value,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+
+    fn internal(err: Box<dyn Error>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Ports {
+    pub other_port: Option<String>,
+    pub decoded_port: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Stats {
+    pub frames: HashMap<String, FrameStats>,
+    pub crc: HashMap<String, CrcStats>,
+    pub sinks: Vec<SinkInfo>,
+    pub writes: HashMap<String, WriteStats>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ConnectQuery {
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct EventsQuery {
+    /// Comma separated, every topic when absent.
+    topics: Option<String>,
+}
+
+async fn get_status(State(state): State<AppStateType>) -> ApiResult<Status> {
+    let status = rpc::status(&state).await.map_err(ApiError::internal)?;
+    Ok(Json(status))
+}
+
+async fn get_devices(State(state): State<AppStateType>) -> ApiResult<Vec<Device>> {
+    let devices = state
+        .ble_manager
+        .get_devices()
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(devices))
+}
+
+async fn get_advertisement(
+    State(state): State<AppStateType>,
+    Path(id): Path<String>,
+) -> ApiResult<AdvertisementData> {
+    let advertisement = state
+        .ble_manager
+        .get_advertisement(&id)
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(advertisement))
+}
+
+async fn start_scan(State(state): State<AppStateType>) -> ApiResult<()> {
+    state
+        .ble_manager
+        .start_scan()
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(()))
+}
+
+async fn stop_scan(State(state): State<AppStateType>) -> ApiResult<()> {
+    state
+        .ble_manager
+        .stop_scan()
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(()))
+}
+
+async fn connect(State(state): State<AppStateType>, Path(id): Path<String>) -> ApiResult<()> {
+    state
+        .ble_manager
+        .connect_device(id)
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(()))
+}
+
+async fn connect_address(
+    State(state): State<AppStateType>,
+    Path(address): Path<String>,
+    Query(query): Query<ConnectQuery>,
+) -> ApiResult<String> {
+    let timeout = query
+        .timeout_ms
+        .map_or(ADDRESS_SCAN_TIMEOUT, Duration::from_millis);
+    let id = state
+        .ble_manager
+        .connect_address(&address, timeout)
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(id))
+}
+
+async fn disconnect(State(state): State<AppStateType>, Path(id): Path<String>) -> ApiResult<()> {
+    state
+        .ble_manager
+        .disconnect_device(id)
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(()))
+}
+
+async fn write(
+    State(state): State<AppStateType>,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> ApiResult<()> {
+    state
+        .ble_manager
+        .write(&id, body.to_vec())
+        .await
+        .map_err(|err| ApiError::new(StatusCode::CONFLICT, err))?;
+    Ok(Json(()))
+}
+
+async fn set_crc_policy(
+    State(state): State<AppStateType>,
+    Path(id): Path<String>,
+    Json(policy): Json<Option<BadFramePolicy>>,
+) -> ApiResult<()> {
+    if let Err(errors) = state.set_crc_policy(&id, policy).await {
+        return Err(ApiError {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            body: json!({ "errors": errors }),
+        });
+    }
+    Ok(Json(()))
+}
+
+async fn get_ports(State(state): State<AppStateType>) -> ApiResult<Ports> {
+    Ok(Json(Ports {
+        other_port: state.other_port.lock().await.clone(),
+        decoded_port: state.decoded_port.lock().await.clone(),
+    }))
+}
+
+async fn get_settings(State(state): State<AppStateType>) -> ApiResult<Settings> {
+    Ok(Json(state.settings().await))
+}
+
+async fn get_profiles(State(state): State<AppStateType>) -> ApiResult<Vec<Profile>> {
+    Ok(Json(state.settings().await.ble.registry().list().to_vec()))
+}
+
+async fn update_settings(
+    State(state): State<AppStateType>,
+    Json(settings): Json<Settings>,
+) -> ApiResult<Settings> {
+    if let Err(errors) = state.update_settings(settings).await {
+        return Err(ApiError {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            body: json!({ "errors": errors }),
+        });
+    }
+    Ok(Json(state.settings().await))
+}
+
+async fn get_stats(State(state): State<AppStateType>) -> ApiResult<Stats> {
+    let frames = state.inspectors.lock().await.stats();
+    let crc = state.crc_checkers.lock().await.stats();
+    let sinks = state.sinks.lock().await.list();
+    let writes = state.ble_manager.write_stats().await;
+    Ok(Json(Stats {
+        frames,
+        crc,
+        sinks,
+        writes,
+    }))
+}
+
+async fn get_metrics(State(state): State<AppStateType>) -> impl IntoResponse {
+    let body = metrics::render(&state).await;
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], body)
+}
+
+async fn events(
+    State(state): State<AppStateType>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let topics = match query.topics {
+        Some(topics) => topics
+            .split(',')
+            .map(|name| name.trim().parse::<Topic>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?,
+        None => vec![
+            Topic::Lifecycle,
+            Topic::Data,
+            Topic::Errors,
+            Topic::Advertisements,
+        ],
+    };
+    let subscription = state.bus.subscribe(&topics);
+
+    let stream = stream::unfold(subscription, |mut subscription| async move {
+        let event = match subscription.recv().await? {
+            Received::Event(event) => Event::default()
+                .event(format!("{:?}", Topic::of(&event)))
+                .json_data(&*event),
+            Received::Lagged { topic, missed } => Event::default()
+                .event("Lagged")
+                .json_data(json!({ "topic": topic, "missed": missed })),
+        };
+        Some((Ok(event.unwrap_or_default()), subscription))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+struct Route {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    content_type: &'static str,
+    handler: MethodRouter<AppStateType>,
+    /// Schema of a successful response.
+    response: Option<Value>,
+    /// Content type and schema of the request body.
+    request: Option<(&'static str, Value)>,
+    /// Name and schema of each query parameter.
+    query: Vec<(&'static str, Value)>,
+}
+
+impl Route {
+    fn returns(mut self, schema: Value) -> Self {
+        self.response = Some(schema);
+        self
+    }
+
+    fn accepts(mut self, content_type: &'static str, schema: Value) -> Self {
+        self.request = Some((content_type, schema));
+        self
+    }
+
+    fn query(mut self, name: &'static str, schema: Value) -> Self {
+        self.query.push((name, schema));
+        self
+    }
+}
+
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(name: &str) -> Value {
+    json!({ "type": "array", "items": schema(name) })
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": kind, "nullable": true })
+}
+
+/// One of the string variants of an enum.
+fn names(variants: &[&str]) -> Value {
+    json!({ "type": "string", "enum": variants })
+}
+
+/// An enum variant carrying data, e.g. `{"Offset": 4}`.
+fn variant(name: &str, schema: Value) -> Value {
+    json!({ "type": "object", "properties": { name: schema }, "required": [name] })
+}
+
+fn optional(name: &str) -> Value {
+    json!({ "allOf": [schema(name)], "nullable": true })
+}
+
+/// Schemas of the bodies the API takes and returns.
+///
+/// Written by hand; the tests check them against serialized values so they
+/// can't drift from the types.
+fn schemas() -> Value {
+    let counter = json!({ "type": "integer", "format": "int64" });
+    let integer = json!({ "type": "integer" });
+    let boolean = json!({ "type": "boolean" });
+    let string = json!({ "type": "string" });
+    let uuid = json!({ "type": "string", "format": "uuid" });
+    let uuids = json!({ "type": "array", "items": uuid });
+    let bytes = json!({ "type": "array", "items": integer });
+    let mut schemas = json!({
+        "Error": {
+            "type": "object",
+            "properties": { "error": string },
+            "required": ["error"],
+        },
+        "Null": {
+            "type": "object",
+            "nullable": true,
+            "description": "Always null",
+        },
+        "DeviceInfo": {
+            "type": "object",
+            "properties": {
+                "manufacturer": nullable("string"),
+                "model": nullable("string"),
+                "serial": nullable("string"),
+                "firmware_revision": nullable("string"),
+                "hardware_revision": nullable("string"),
+                "battery": nullable("integer"),
+            },
+        },
+        "Device": {
+            "type": "object",
+            "properties": {
+                "id": string,
+                "name": string,
+                "rssi": nullable("integer"),
+                "tx_power": nullable("integer"),
+                "last_seen": counter,
+                "services": uuids,
+                "alias": nullable("string"),
+                "color": nullable("string"),
+                "notes": nullable("string"),
+                "profile": nullable("string"),
+                "info": optional("DeviceInfo"),
+            },
+            "required": ["id", "name"],
+        },
+        "SinkInfo": {
+            "type": "object",
+            "properties": {
+                "name": string,
+                "device": nullable("string"),
+                "channel": nullable("string"),
+                "config": schema("SinkConfig"),
+                "location": nullable("string"),
+                "written": counter,
+                "dropped": counter,
+                "errors": counter,
+                "closed": boolean,
+            },
+            "required": ["name", "config", "written", "dropped", "errors", "closed"],
+        },
+        "Status": {
+            "type": "object",
+            "properties": {
+                "devices": array_of("Device"),
+                "other_port": nullable("string"),
+                "decoded_port": nullable("string"),
+                "sinks": array_of("SinkInfo"),
+            },
+            "required": ["devices", "sinks"],
+        },
+        "ManufacturerData": {
+            "type": "object",
+            "properties": {
+                "company_id": integer,
+                "data": bytes,
+                "hex": string,
+            },
+        },
+        "ServiceData": {
+            "type": "object",
+            "properties": {
+                "uuid": uuid,
+                "data": bytes,
+                "hex": string,
+            },
+        },
+        "Advertisement": {
+            "type": "object",
+            "properties": {
+                "id": string,
+                "name": nullable("string"),
+                "rssi": nullable("integer"),
+                "tx_power": nullable("integer"),
+                "services": uuids,
+                "manufacturer_data": array_of("ManufacturerData"),
+                "service_data": array_of("ServiceData"),
+            },
+            "required": ["id", "services", "manufacturer_data", "service_data"],
+        },
+        "Ports": {
+            "type": "object",
+            "properties": {
+                "other_port": nullable("string"),
+                "decoded_port": nullable("string"),
+            },
+        },
+        "CrcPolicy": optional("BadFramePolicy"),
+        "WriteStats": {
+            "type": "object",
+            "properties": {
+                "queue_depth": counter,
+                "writes": counter,
+                "chunks": counter,
+                "bytes": counter,
+                "retries": counter,
+                "failures": counter,
+                "rejected": counter,
+            },
+        },
+        "FrameStats": {
+            "type": "object",
+            "properties": {
+                "received": counter,
+                "missing": counter,
+                "out_of_order": counter,
+            },
+        },
+        "CrcStats": {
+            "type": "object",
+            "properties": {
+                "valid": counter,
+                "invalid": counter,
+            },
+        },
+        "Stats": {
+            "type": "object",
+            "properties": {
+                "frames": { "type": "object", "additionalProperties": schema("FrameStats") },
+                "crc": { "type": "object", "additionalProperties": schema("CrcStats") },
+                "sinks": array_of("SinkInfo"),
+                "writes": { "type": "object", "additionalProperties": schema("WriteStats") },
+            },
+        },
+        "WriteBody": {
+            "type": "string",
+            "format": "binary",
+            "description": "Bytes written to the device as they are",
+        },
+    });
+    let settings = settings_schemas();
+    schemas
+        .as_object_mut()
+        .unwrap()
+        .extend(settings.as_object().unwrap().clone());
+    schemas
+}
+
+/// Schemas of [`Settings`] and the types in it.
+fn settings_schemas() -> Value {
+    let counter = json!({ "type": "integer", "format": "int64" });
+    let integer = json!({ "type": "integer" });
+    let boolean = json!({ "type": "boolean" });
+    let string = json!({ "type": "string" });
+    let uuid = json!({ "type": "string", "format": "uuid" });
+    let bytes = json!({ "type": "array", "items": integer });
+    json!({
+        "SinkConfig": {
+            "oneOf": [
+                variant("Port", json!({
+                    "type": "object",
+                    "properties": { "index": integer },
+                    "required": ["index"],
+                })),
+                variant("Serial", string.clone()),
+                variant("File", string.clone()),
+                variant("Tcp", string.clone()),
+                variant("Decoded", schema("DecoderConfig")),
+            ],
+        },
+        "Endianness": names(&["Little", "Big"]),
+        "Framing": {
+            "oneOf": [
+                names(&["Notification", "Slip", "Cobs"]),
+                variant("LengthPrefixed", json!({
+                    "type": "object",
+                    "properties": {
+                        "offset": integer,
+                        "width": integer,
+                        "endianness": schema("Endianness"),
+                        "includes_header": boolean,
+                    },
+                    "required": ["width"],
+                })),
+                variant("Delimiter", bytes.clone()),
+            ],
+        },
+        "PollConfig": {
+            "type": "object",
+            "properties": {
+                "interval_ms": counter,
+                "jitter_ms": counter,
+                "changes_only": boolean,
+            },
+            "required": ["interval_ms"],
+        },
+        "Profile": {
+            "type": "object",
+            "properties": {
+                "name": string,
+                "service_uuid": uuid,
+                "notify_uuid": uuid,
+                "write_uuid": { "type": "string", "format": "uuid", "nullable": true },
+                "framing": {
+                    "allOf": [schema("Framing")],
+                    "nullable": true,
+                    "description": "Framing of the profile's devices, the settings' one when null",
+                },
+                "poll": optional("PollConfig"),
+            },
+            "required": ["name", "service_uuid", "notify_uuid"],
+        },
+        "AdvertisementConfig": {
+            "type": "object",
+            "properties": {
+                "source": names(&["ManufacturerData", "ServiceData"]),
+                "company_id": nullable("integer"),
+                "service_uuid": { "type": "string", "format": "uuid", "nullable": true },
+                "addresses": { "type": "array", "items": string },
+                "header": boolean,
+                "dedup_window_ms": counter,
+            },
+        },
+        "ChannelConfig": {
+            "type": "object",
+            "properties": {
+                "name": string,
+                "service_uuid": uuid,
+                "characteristic_uuid": uuid,
+                "tag": integer,
+                "output": optional("SinkConfig"),
+                "poll": optional("PollConfig"),
+            },
+            "required": ["name", "service_uuid", "characteristic_uuid", "tag"],
+        },
+        "WriteConfig": {
+            "type": "object",
+            "properties": {
+                "att_mtu": integer,
+                "with_response": boolean,
+                "chunk_delay_ms": counter,
+                "retries": integer,
+                "retry_delay_ms": counter,
+                "queue_capacity": integer,
+            },
+        },
+        "BleSettings": {
+            "type": "object",
+            "properties": {
+                "service_uuid": uuid,
+                "characteristic_uuid": uuid,
+                "name_filter": string,
+                "device_timeout_secs": counter,
+                "advertisement": optional("AdvertisementConfig"),
+                "profiles": array_of("Profile"),
+                "low_battery_percent": integer,
+                "channels": array_of("ChannelConfig"),
+                "channel_mode": names(&["Separate", "Multiplexed"]),
+                "write": schema("WriteConfig"),
+            },
+        },
+        "PortSettings": {
+            "type": "object",
+            "properties": {
+                "pair": integer,
+                "decoded_pair": integer,
+                "baud_rate": integer,
+                "other_port_name": nullable("string"),
+                "remove_on_exit": boolean,
+            },
+        },
+        "CounterConfig": {
+            "type": "object",
+            "properties": {
+                "offset": integer,
+                "width": integer,
+                "endianness": schema("Endianness"),
+                "gap_marker": { "type": "array", "items": integer, "nullable": true },
+            },
+            "required": ["offset", "width"],
+        },
+        "BadFramePolicy": {
+            "oneOf": [
+                names(&["Forward", "Drop"]),
+                {
+                    "type": "object",
+                    "properties": { "Mark": bytes },
+                    "required": ["Mark"],
+                    "description": "Forward the frame preceded by these bytes",
+                },
+            ],
+        },
+        "CrcConfig": {
+            "type": "object",
+            "properties": {
+                "width": names(&["Crc8", "Crc16", "Crc32"]),
+                "polynomial": counter,
+                "init": counter,
+                "xor_out": counter,
+                "reflect": boolean,
+                "location": {
+                    "oneOf": [names(&["End"]), variant("Offset", integer.clone())],
+                },
+                "start": integer,
+                "endianness": schema("Endianness"),
+                "policy": schema("BadFramePolicy"),
+            },
+            "required": ["width", "polynomial"],
+        },
+        "DecoderConfig": {
+            "type": "object",
+            "properties": {
+                "channels": integer,
+                "sample_width": integer,
+                "endianness": schema("Endianness"),
+                "signed": boolean,
+                "header_len": integer,
+                "counter_offset": nullable("integer"),
+                "counter_width": integer,
+                "format": names(&["Csv", "JsonLines"]),
+                "output": { "oneOf": [names(&["Port"]), variant("File", string.clone())] },
+            },
+            "required": ["channels", "sample_width"],
+        },
+        "RpcSettings": {
+            "type": "object",
+            "properties": {
+                "enabled": boolean,
+                "path": nullable("string"),
+            },
+        },
+        "HttpSettings": {
+            "type": "object",
+            "properties": {
+                "enabled": boolean,
+                "address": { "type": "string", "description": "Loopback only, e.g. 127.0.0.1:8787" },
+            },
+        },
+        "UuidProfile": {
+            "type": "object",
+            "properties": {
+                "service_uuid": uuid,
+                "characteristic_uuid": uuid,
+            },
+            "required": ["service_uuid", "characteristic_uuid"],
+        },
+        "KnownDevice": {
+            "type": "object",
+            "properties": {
+                "address": string,
+                "alias": nullable("string"),
+                "color": nullable("string"),
+                "notes": nullable("string"),
+                "pair": nullable("integer"),
+                "port": nullable("string"),
+                "profile": optional("UuidProfile"),
+                "port_name": nullable("string"),
+                "framing": optional("Framing"),
+                "crc_policy": optional("BadFramePolicy"),
+                "decoder": optional("DecoderConfig"),
+                "auto_connect": boolean,
+            },
+            "required": ["address"],
+        },
+        "Settings": {
+            "type": "object",
+            "description": "Every setting; missing ones take their defaults",
+            "properties": {
+                "version": integer,
+                "ble": schema("BleSettings"),
+                "port": schema("PortSettings"),
+                "resource_path": nullable("string"),
+                "counter": optional("CounterConfig"),
+                "framing": schema("Framing"),
+                "crc": optional("CrcConfig"),
+                "decoder": optional("DecoderConfig"),
+                "rpc": schema("RpcSettings"),
+                "http": schema("HttpSettings"),
+                "known_devices": array_of("KnownDevice"),
+            },
+        },
+    })
+}
+
+fn routes() -> Vec<Route> {
+    let route = |method, path, summary, content_type, handler| Route {
+        method,
+        path,
+        summary,
+        content_type,
+        handler,
+        response: None,
+        request: None,
+        query: Vec::new(),
+    };
+    vec![
+        route(
+            "get",
+            "/status",
+            "Devices, ports and sinks",
+            JSON,
+            get(get_status),
+        )
+        .returns(schema("Status")),
+        route(
+            "get",
+            "/devices",
+            "Discovered devices",
+            JSON,
+            get(get_devices),
+        )
+        .returns(array_of("Device")),
+        route(
+            "get",
+            "/devices/:id/advertisement",
+            "Latest advertisement of a device with hex views",
+            JSON,
+            get(get_advertisement),
+        )
+        .returns(schema("Advertisement")),
+        route("post", "/scan", "Start scanning", JSON, post(start_scan))
+        .returns(schema("Null")),
+        route("delete", "/scan", "Stop scanning", JSON, delete(stop_scan))
+        .returns(schema("Null")),
+        route(
+            "post",
+            "/devices/:id/connect",
+            "Connect to a device",
+            JSON,
+            post(connect),
+        )
+        .returns(schema("Null")),
+        route(
+            "post",
+            "/addresses/:address/connect",
+            "Connect by address without waiting for discovery; `?timeout_ms=` bounds the scan",
+            JSON,
+            post(connect_address),
+        )
+        .returns(json!({ "type": "string", "description": "Id of the device" }))
+        .query("timeout_ms", json!({ "type": "integer", "format": "int64" })),
+        route(
+            "post",
+            "/devices/:id/disconnect",
+            "Disconnect a device",
+            JSON,
+            post(disconnect),
+        )
+        .returns(schema("Null")),
+        route(
+            "post",
+            "/devices/:id/write",
+            "Queue the request body to be written to a connected device; 409 when it can't take it",
+            JSON,
+            post(write),
+        )
+        .accepts(OCTET_STREAM, schema("WriteBody"))
+        .returns(schema("Null")),
+        route(
+            "put",
+            "/devices/:id/crc_policy",
+            "Save what happens to a known device's frames failing their checksum; null uses the CRC settings' policy",
+            JSON,
+            put(set_crc_policy),
+        )
+        .accepts(JSON, schema("CrcPolicy"))
+        .returns(schema("Null")),
+        route(
+            "get",
+            "/ports",
+            "Raw and decoded COM ports",
+            JSON,
+            get(get_ports),
+        )
+        .returns(schema("Ports")),
+        route(
+            "get",
+            "/settings",
+            "Current settings",
+            JSON,
+            get(get_settings),
+        )
+        .returns(schema("Settings")),
+        route(
+            "put",
+            "/settings",
+            "Validate, apply and save settings; 422 lists the invalid fields",
+            JSON,
+            put(update_settings),
+        )
+        .accepts(JSON, schema("Settings"))
+        .returns(schema("Settings")),
+        route(
+            "get",
+            "/profiles",
+            "Profiles in detection order",
+            JSON,
+            get(get_profiles),
+        )
+        .returns(array_of("Profile")),
+        route(
+            "get",
+            "/stats",
+            "Frame, CRC, sink and write queue counters per device",
+            JSON,
+            get(get_stats),
+        )
+        .returns(schema("Stats")),
+        route(
+            "get",
+            "/events",
+            "Device events as server-sent events, filtered by `?topics=Lifecycle,Data,Errors,Advertisements`",
+            EVENT_STREAM,
+            get(events),
+        )
+        .query("topics", json!({ "type": "string" })),
+        route(
+            "get",
+            "/metrics",
+            "Connection, notification and sink counters for Prometheus",
+            metrics::CONTENT_TYPE,
+            get(get_metrics),
+        )
+        .returns(json!({ "type": "string" })),
+    ]
+}
+
+/// OpenAPI 3 description of `routes`.
+fn openapi(routes: &[Route]) -> Value {
+    let mut paths = serde_json::Map::new();
+    for route in routes {
+        let mut path = Vec::new();
+        let mut parameters = Vec::new();
+        for segment in route.path.split('/') {
+            match segment.strip_prefix(':') {
+                Some(name) => {
+                    path.push(format!("{{{}}}", name));
+                    parameters.push(json!({
+                        "name": name,
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" },
+                    }));
+                }
+                None => path.push(segment.to_string()),
+            }
+        }
+        for (name, schema) in &route.query {
+            parameters.push(json!({
+                "name": name,
+                "in": "query",
+                "required": false,
+                "schema": schema,
+            }));
+        }
+        let response = match &route.response {
+            Some(schema) => json!({ "schema": schema }),
+            None => json!({}),
+        };
+
+        let mut operation = json!({
+            "summary": route.summary,
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": "Success",
+                    "content": { route.content_type: response },
+                },
+                "default": {
+                    "description": "Failure with an `error` message",
+                    "content": { JSON: { "schema": schema("Error") } },
+                },
+            },
+        });
+        if let Some((content_type, schema)) = &route.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { *content_type: { "schema": schema } },
+            });
+        }
+
+        let item = paths.entry(path.join("/")).or_insert_with(|| json!({}));
+        item[route.method] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "ble2serial",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": schemas() },
+    })
+}
+
+pub fn router(state: AppStateType) -> Router {
+    let routes = routes();
+    let document = openapi(&routes);
+
+    let mut router = Router::new();
+    for route in routes {
+        router = router.route(route.path, route.handler);
+    }
+    router
+        .route("/openapi.json", get(move || async move { Json(document) }))
+        .with_state(state)
+}
+
+/// Starts the HTTP API in the background when enabled in `settings`.
+pub fn spawn_server(state: AppStateType, settings: &HttpSettings) {
+    if !settings.enabled {
+        return;
+    }
+    let address: SocketAddr = match settings.address.parse() {
+        Ok(address) => address,
+        Err(err) => {
+            error!("Invalid HTTP address {}: {}", settings.address, err);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        debug!("HTTP API listening on {}", address);
+        let server = axum::Server::try_bind(&address)
+            .map(|server| server.serve(router(state).into_make_service()));
+        let result = match server {
+            Ok(server) => server.await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            error!("HTTP server stopped: {}", err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile;
+
+    const SERVICE: &str = "0000ffa0-0000-1000-8000-00805f9b34fb";
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+
+    /// Checks `value` against `schema`, following references into
+    /// `components`. Every field must be documented and every documented
+    /// field present, so a field added to or removed from a type fails.
+    fn conforms(
+        value: &Value,
+        schema: &Value,
+        components: &Value,
+        path: &str,
+    ) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            if components[name].is_null() {
+                return Err(format!("{} refers to missing {}", path, name));
+            }
+            return conforms(value, &components[name], components, path);
+        }
+        if value.is_null() {
+            return match schema["nullable"].as_bool() {
+                Some(true) => Ok(()),
+                _ => Err(format!("{} is null", path)),
+            };
+        }
+        for schema in schema["allOf"].as_array().into_iter().flatten() {
+            conforms(value, schema, components, path)?;
+        }
+        if let Some(schemas) = schema["oneOf"].as_array() {
+            let matching = schemas
+                .iter()
+                .filter(|schema| conforms(value, schema, components, path).is_ok())
+                .count();
+            if matching != 1 {
+                return Err(format!("{} matches {} of {}", path, matching, schema));
+            }
+        }
+        if let Some(kind) = schema["type"].as_str() {
+            let matches = match kind {
+                "object" => value.is_object(),
+                "array" => value.is_array(),
+                "string" => value.is_string(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "boolean" => value.is_boolean(),
+                _ => false,
+            };
+            if !matches {
+                return Err(format!("{} is not {}: {}", path, kind, value));
+            }
+        }
+        if let Some(variants) = schema["enum"].as_array() {
+            if !variants.contains(value) {
+                return Err(format!("{} is not one of {:?}: {}", path, variants, value));
+            }
+        }
+        if let (Some(properties), Some(object)) =
+            (schema["properties"].as_object(), value.as_object())
+        {
+            if let Some(key) = properties.keys().find(|key| !object.contains_key(*key)) {
+                return Err(format!("{}.{} is documented but missing", path, key));
+            }
+            for (key, field) in object {
+                let property = properties
+                    .get(key)
+                    .ok_or_else(|| format!("{}.{} is not documented", path, key))?;
+                conforms(field, property, components, &format!("{}.{}", path, key))?;
+            }
+        }
+        if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+            for (i, item) in array.iter().enumerate() {
+                conforms(item, items, components, &format!("{}.{}", path, i))?;
+            }
+        }
+        if let (Some(values), Some(object)) =
+            (schema.get("additionalProperties"), value.as_object())
+        {
+            for (key, field) in object {
+                conforms(field, values, components, &format!("{}.{}", path, key))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check<T: serde::Serialize>(name: &str, value: &T) {
+        let components = schemas();
+        let value = serde_json::to_value(value).unwrap();
+        if let Err(err) = conforms(&value, &schema(name), &components, name) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Settings with every optional part set.
+    fn populated_settings() -> Settings {
+        let settings = json!({
+            "ble": {
+                "advertisement": { "company_id": 89, "service_uuid": SERVICE },
+                "profiles": [{
+                    "name": "Probe",
+                    "service_uuid": SERVICE,
+                    "notify_uuid": SERVICE,
+                    "write_uuid": SERVICE,
+                    "framing": { "Delimiter": [10] },
+                    "poll": { "interval_ms": 100 },
+                }],
+                "channels": [{
+                    "name": "temperature",
+                    "service_uuid": SERVICE,
+                    "characteristic_uuid": SERVICE,
+                    "tag": 1,
+                    "output": { "Tcp": "127.0.0.1:5000" },
+                    "poll": { "interval_ms": 1000 },
+                }],
+            },
+            "port": { "other_port_name": "COM7" },
+            "resource_path": "/opt/com0com",
+            "counter": { "offset": 0, "width": 2, "gap_marker": [238] },
+            "framing": { "LengthPrefixed": { "width": 1 } },
+            "crc": {
+                "width": "Crc16",
+                "polynomial": 32773,
+                "location": { "Offset": 4 },
+                "policy": { "Mark": [255] },
+            },
+            "decoder": {
+                "channels": 2,
+                "sample_width": 2,
+                "counter_offset": 0,
+                "output": { "File": "decoded.csv" },
+            },
+            "rpc": { "path": "/tmp/ble2serial.sock" },
+            "known_devices": [{
+                "address": DEVICE,
+                "alias": "Left",
+                "color": "#3f2aff",
+                "notes": "Spare",
+                "pair": 2,
+                "port": "COM9",
+                "profile": { "service_uuid": SERVICE, "characteristic_uuid": SERVICE },
+                "port_name": "COM3",
+                "framing": "Slip",
+                "crc_policy": "Drop",
+                "decoder": { "channels": 1, "sample_width": 1 },
+            }],
+        });
+        serde_json::from_value(settings).unwrap()
+    }
+
+    #[test]
+    fn settings_match_their_schema() {
+        check("Settings", &Settings::default());
+        check("Settings", &populated_settings());
+        for profile in profile::builtin() {
+            check("Profile", &profile);
+        }
+    }
+
+    #[test]
+    fn bodies_match_their_schemas() {
+        let device: Device = serde_json::from_value(json!({
+            "id": DEVICE,
+            "name": "BioSignal",
+            "rssi": -60,
+            "services": [SERVICE],
+            "info": { "battery": 80 },
+        }))
+        .unwrap();
+        let sink = SinkInfo {
+            name: "decoded".to_string(),
+            device: Some(DEVICE.to_string()),
+            channel: None,
+            config: serde_json::from_value(
+                json!({ "Decoded": { "channels": 1, "sample_width": 2 } }),
+            )
+            .unwrap(),
+            location: Some("decoded.csv".to_string()),
+            written: 1,
+            dropped: 0,
+            errors: 0,
+            closed: false,
+        };
+        check(
+            "Status",
+            &Status {
+                devices: vec![device],
+                other_port: Some("COM7".to_string()),
+                decoded_port: None,
+                sinks: vec![sink.clone()],
+            },
+        );
+
+        let stats: Stats = serde_json::from_value(json!({
+            "frames": { DEVICE: { "received": 3, "missing": 1, "out_of_order": 0 } },
+            "crc": { DEVICE: { "valid": 2, "invalid": 1 } },
+            "sinks": [],
+            "writes": { DEVICE: {
+                "queue_depth": 0, "writes": 1, "chunks": 2, "bytes": 30,
+                "retries": 0, "failures": 0, "rejected": 0,
+            } },
+        }))
+        .unwrap();
+        check(
+            "Stats",
+            &Stats {
+                sinks: vec![sink],
+                ..stats
+            },
+        );
+
+        let advertisement: AdvertisementData = serde_json::from_value(json!({
+            "id": DEVICE,
+            "name": null,
+            "rssi": -70,
+            "tx_power": null,
+            "services": [SERVICE],
+            "manufacturer_data": [{ "company_id": 89, "data": [1, 2], "hex": "0102" }],
+            "service_data": [{ "uuid": SERVICE, "data": [3], "hex": "03" }],
+        }))
+        .unwrap();
+        check("Advertisement", &advertisement);
+
+        check("CrcPolicy", &Some(BadFramePolicy::Mark(vec![0xEE])));
+        check("CrcPolicy", &None::<BadFramePolicy>);
+    }
+}
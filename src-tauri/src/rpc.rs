@@ -0,0 +1,404 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::{debug, error};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::app::AppStateType;
+use crate::ble::{Device, ADDRESS_SCAN_TIMEOUT};
+use crate::bus::{Received, Topic};
+use crate::crc::BadFramePolicy;
+use crate::settings::RpcSettings;
+use crate::sink::SinkInfo;
+
+/// Replies and notifications a connection may queue before the reader waits.
+const CONNECTION_BUFFER: usize = 256;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, serde::Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Absent for notifications, which get no reply; `Some(Null)` for an
+    /// explicit `"id": null`.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Wraps whatever is there, `null` included, so only a missing field is `None`.
+fn present<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct IdParams {
+    id: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct WriteParams {
+    id: String,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CrcPolicyParams {
+    id: String,
+    /// The policy of the CRC settings when absent.
+    #[serde(default)]
+    policy: Option<BadFramePolicy>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AddressParams {
+    address: String,
+    /// How long to scan when the OS doesn't know the device.
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SubscribeParams {
+    topics: Vec<Topic>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Status {
+    pub devices: Vec<Device>,
+    pub other_port: Option<String>,
+    pub decoded_port: Option<String>,
+    pub sinks: Vec<SinkInfo>,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn server(err: Box<dyn Error>) -> Self {
+        Self::new(SERVER_ERROR, err)
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+/// `ble2serial.sock` in the user's runtime directory, or the temp directory
+/// when there is none; the `ble2serial` pipe on Windows.
+pub fn default_path() -> String {
+    if cfg!(windows) {
+        r"\\.\pipe\ble2serial".to_string()
+    } else {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("ble2serial.sock")
+            .display()
+            .to_string()
+    }
+}
+
+pub async fn status(state: &AppStateType) -> Result<Status, Box<dyn Error>> {
+    let devices = state.ble_manager.get_devices().await?;
+    let sinks = state.sinks.lock().await.list();
+    Ok(Status {
+        devices,
+        other_port: state.other_port.lock().await.clone(),
+        decoded_port: state.decoded_port.lock().await.clone(),
+        sinks,
+    })
+}
+
+async fn call(state: &AppStateType, method: &str, params_value: Value) -> Result<Value, RpcError> {
+    let result = match method {
+        "start_scan" => {
+            state
+                .ble_manager
+                .start_scan()
+                .await
+                .map_err(RpcError::server)?;
+            Value::Null
+        }
+        "stop_scan" => {
+            state
+                .ble_manager
+                .stop_scan()
+                .await
+                .map_err(RpcError::server)?;
+            Value::Null
+        }
+        "connect" => {
+            let IdParams { id } = params(params_value)?;
+            state
+                .ble_manager
+                .connect_device(id)
+                .await
+                .map_err(RpcError::server)?;
+            Value::Null
+        }
+        "connect_address" => {
+            let AddressParams {
+                address,
+                timeout_ms,
+            } = params(params_value)?;
+            let timeout = timeout_ms.map_or(ADDRESS_SCAN_TIMEOUT, Duration::from_millis);
+            let id = state
+                .ble_manager
+                .connect_address(&address, timeout)
+                .await
+                .map_err(RpcError::server)?;
+            json!(id)
+        }
+        "disconnect" => {
+            let IdParams { id } = params(params_value)?;
+            state
+                .ble_manager
+                .disconnect_device(id)
+                .await
+                .map_err(RpcError::server)?;
+            Value::Null
+        }
+        "write" => {
+            let WriteParams { id, data } = params(params_value)?;
+            state
+                .ble_manager
+                .write(&id, data)
+                .await
+                .map_err(RpcError::server)?;
+            Value::Null
+        }
+        "get_devices" => {
+            let devices = state
+                .ble_manager
+                .get_devices()
+                .await
+                .map_err(RpcError::server)?;
+            json!(devices)
+        }
+        "get_advertisement" => {
+            let IdParams { id } = params(params_value)?;
+            let advertisement = state
+                .ble_manager
+                .get_advertisement(&id)
+                .await
+                .map_err(RpcError::server)?;
+            json!(advertisement)
+        }
+        "set_crc_policy" => {
+            let CrcPolicyParams { id, policy } = params(params_value)?;
+            state.set_crc_policy(&id, policy).await.map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                RpcError::new(INVALID_PARAMS, errors.join(", "))
+            })?;
+            Value::Null
+        }
+        "get_other_port" => json!(*state.other_port.lock().await),
+        "status" => json!(status(state).await.map_err(RpcError::server)?),
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {}", method),
+            ))
+        }
+    };
+    Ok(result)
+}
+
+/// Forwards events on `topics` to the client as `event` notifications.
+async fn forward_events(
+    state: AppStateType,
+    topics: Vec<Topic>,
+    tx: mpsc::Sender<Value>,
+) -> JoinHandle<()> {
+    let mut events = state.bus.subscribe(&topics);
+    tokio::spawn(async move {
+        while let Some(received) = events.recv().await {
+            let params = match received {
+                Received::Event(event) => json!({ "topic": Topic::of(&event), "event": &*event }),
+                Received::Lagged { topic, missed } => json!({ "topic": topic, "missed": missed }),
+            };
+            let notification = json!({ "jsonrpc": "2.0", "method": "event", "params": params });
+            if tx.send(notification).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Serves newline-delimited JSON-RPC 2.0 requests until the client hangs up.
+async fn handle_connection<S>(state: AppStateType, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let (tx, mut rx) = mpsc::channel::<Value>(CONNECTION_BUFFER);
+
+    let writer_task = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let line = format!("{}\n", message);
+            if let Err(err) = writer.write_all(line.as_bytes()).await {
+                debug!("RPC client dropped: {}", err);
+                break;
+            }
+        }
+    });
+
+    let mut subscription: Option<JoinHandle<()>> = None;
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                debug!("Error reading RPC request: {}", err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match serde_json::from_str::<Value>(&line) {
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(err) => {
+                    let reply = response(Value::Null, Err(RpcError::new(INVALID_REQUEST, err)));
+                    let _ = tx.send(reply).await;
+                    continue;
+                }
+            },
+            Err(err) => {
+                let reply = response(Value::Null, Err(RpcError::new(PARSE_ERROR, err)));
+                let _ = tx.send(reply).await;
+                continue;
+            }
+        };
+
+        let result = if request.jsonrpc != "2.0" {
+            Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""))
+        } else {
+            match request.method.as_str() {
+                "subscribe" => match params::<SubscribeParams>(request.params) {
+                    Ok(SubscribeParams { topics }) => {
+                        if let Some(task) = subscription.take() {
+                            task.abort();
+                        }
+                        subscription =
+                            Some(forward_events(state.clone(), topics, tx.clone()).await);
+                        Ok(Value::Null)
+                    }
+                    Err(err) => Err(err),
+                },
+                "unsubscribe" => {
+                    let subscribed = subscription.take().map(|task| task.abort()).is_some();
+                    Ok(json!(subscribed))
+                }
+                method => call(&state, method, request.params).await,
+            }
+        };
+
+        if let Some(id) = request.id {
+            if tx.send(response(id, result)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    if let Some(task) = subscription {
+        task.abort();
+    }
+    drop(tx);
+    let _ = writer_task.await;
+}
+
+#[cfg(unix)]
+async fn serve(state: AppStateType, path: String) -> Result<(), Box<dyn Error>> {
+    use std::fs::Permissions;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use tokio::net::{UnixListener, UnixStream};
+
+    // A socket left behind by a previous run would make bind fail, but one
+    // that still accepts connections belongs to a running instance.
+    if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path).into());
+        }
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(format!("{} is in use by another instance", path).into());
+        }
+        std::fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    // Anyone who can connect can drive the bridge.
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    debug!("RPC listening on {}", path);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(handle_connection(state.clone(), stream));
+    }
+}
+
+#[cfg(windows)]
+async fn serve(state: AppStateType, path: String) -> Result<(), Box<dyn Error>> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&path)?;
+    debug!("RPC listening on {}", path);
+
+    loop {
+        server.connect().await?;
+        let client = server;
+        server = ServerOptions::new().create(&path)?;
+        tokio::spawn(handle_connection(state.clone(), client));
+    }
+}
+
+/// Starts the control socket in the background when enabled in `settings`.
+pub fn spawn_server(state: AppStateType, settings: &RpcSettings) {
+    if !settings.enabled {
+        return;
+    }
+    let path = settings.path.clone().unwrap_or_else(default_path);
+    tokio::spawn(async move {
+        if let Err(err) = serve(state, path).await {
+            error!("RPC server stopped: {}", err);
+        }
+    });
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use std::error::Error;
+
+use btleplug::api::{Characteristic, ValueNotification};
+use futures::stream::{Stream, StreamExt};
+use log::{debug, error};
+use tokio::task::JoinHandle;
+
+use crate::ble::{BleEvent, DeviceCounters};
+use crate::bus::EventBus;
+use crate::write_queue::WriteQueue;
+
+/// Everything running on behalf of one connected device.
+///
+/// Closing the handle cancels its tasks and waits for them to finish, so no
+/// data from the old connection arrives after it returns.
+pub struct SessionHandle {
+    id: String,
+    /// The characteristic the device is subscribed to, if any.
+    characteristic: Option<Characteristic>,
+    /// Writes data to the device, if it can be written to.
+    writer: Option<WriteQueue>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SessionHandle {
+    /// A session that is connected but doesn't forward anything.
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            characteristic: None,
+            writer: None,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Forwards `notifications` to the bus as the device's data until the
+    /// stream ends or the session is closed.
+    pub fn forward<S>(
+        id: String,
+        characteristic: Option<Characteristic>,
+        mut notifications: S,
+        bus: EventBus,
+        counters: Arc<DeviceCounters>,
+    ) -> Self
+    where
+        S: Stream<Item = ValueNotification> + Send + Unpin + 'static,
+    {
+        let notification_id = id.clone();
+        let task = tokio::spawn(async move {
+            while let Some(notification) = notifications.next().await {
+                counters.count_notification(notification.value.len());
+                bus.publish(BleEvent::DeviceNotification(
+                    notification_id.clone(),
+                    notification.value,
+                ));
+            }
+            debug!("Notifications from {} ended", notification_id);
+        });
+
+        Self {
+            id,
+            characteristic,
+            writer: None,
+            tasks: vec![task],
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn characteristic(&self) -> Option<&Characteristic> {
+        self.characteristic.as_ref()
+    }
+
+    pub fn with_writer(mut self, writer: Option<WriteQueue>) -> Self {
+        self.writer = writer;
+        self
+    }
+
+    /// Adds tasks that run on behalf of the device, such as polling, so
+    /// closing the session cancels them too.
+    pub fn with_tasks(mut self, tasks: Vec<JoinHandle<()>>) -> Self {
+        self.tasks.extend(tasks);
+        self
+    }
+
+    pub fn write_characteristic(&self) -> Option<&Characteristic> {
+        self.writer.as_ref().map(|writer| writer.characteristic())
+    }
+
+    /// Queues `data` to be written to the device.
+    pub fn write(&self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        match &self.writer {
+            Some(writer) => writer.push(data),
+            None => Err(format!("{} can't be written to", self.id).into()),
+        }
+    }
+
+    /// Whether any of the session's tasks is still running.
+    pub fn is_active(&self) -> bool {
+        self.tasks.iter().any(|task| !task.is_finished())
+    }
+
+    pub async fn close(self) {
+        if let Some(writer) = self.writer {
+            writer.close().await;
+        }
+        for task in &self.tasks {
+            task.abort();
+        }
+        for task in self.tasks {
+            if let Err(err) = task.await {
+                if !err.is_cancelled() {
+                    error!("Session task of {} failed: {}", self.id, err);
+                }
+            }
+        }
+    }
+}
+
+/// The open session of every connected device.
+#[derive(Default)]
+pub struct Sessions {
+    sessions: HashMap<String, SessionHandle>,
+}
+
+impl Sessions {
+    /// Registers `session`, closing any session the device still had.
+    pub async fn open(&mut self, session: SessionHandle) {
+        if let Some(old) = self.sessions.remove(session.id()) {
+            debug!("Closing stale session of {}", old.id());
+            old.close().await;
+        }
+        self.sessions.insert(session.id().to_string(), session);
+    }
+
+    /// Removes the session without closing it.
+    pub fn take(&mut self, id: &str) -> Option<SessionHandle> {
+        self.sessions.remove(id)
+    }
+
+    pub async fn close(&mut self, id: &str) -> bool {
+        match self.sessions.remove(id) {
+            Some(session) => {
+                session.close().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn close_all(&mut self) {
+        for (_, session) in self.sessions.drain() {
+            session.close().await;
+        }
+    }
+
+    pub fn write(&self, id: &str, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        match self.sessions.get(id) {
+            Some(session) => session.write(data),
+            None => Err(format!("{} is not connected", id).into()),
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.sessions.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::bus::{Received, Subscription, Topic};
+
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+
+    /// Stands in for a peripheral: notifications sent on the returned sender
+    /// come out of the session's stream.
+    fn connect(
+        bus: &EventBus,
+        counters: &Arc<DeviceCounters>,
+    ) -> (UnboundedSender<ValueNotification>, SessionHandle) {
+        let (tx, rx) = unbounded();
+        let session =
+            SessionHandle::forward(DEVICE.to_string(), None, rx, bus.clone(), counters.clone());
+        (tx, session)
+    }
+
+    /// A notification stream that records when the task polling it drops it.
+    struct Watched {
+        rx: UnboundedReceiver<ValueNotification>,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Stream for Watched {
+        type Item = ValueNotification;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            self.rx.poll_next_unpin(cx)
+        }
+    }
+
+    impl Drop for Watched {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Like `connect`, but also returns a flag that is set once the session's
+    /// task has dropped its stream.
+    fn connect_watched(
+        bus: &EventBus,
+        counters: &Arc<DeviceCounters>,
+    ) -> (
+        UnboundedSender<ValueNotification>,
+        Arc<AtomicBool>,
+        SessionHandle,
+    ) {
+        let (tx, rx) = unbounded();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let stream = Watched {
+            rx,
+            dropped: dropped.clone(),
+        };
+        let session = SessionHandle::forward(
+            DEVICE.to_string(),
+            None,
+            stream,
+            bus.clone(),
+            counters.clone(),
+        );
+        (tx, dropped, session)
+    }
+
+    fn notification(value: &[u8]) -> ValueNotification {
+        ValueNotification {
+            uuid: Uuid::nil(),
+            value: value.to_vec(),
+        }
+    }
+
+    /// Collects the payloads published until the bus has been quiet for a while.
+    async fn drain(events: &mut Subscription) -> Vec<Vec<u8>> {
+        let mut payloads = Vec::new();
+        while let Ok(Some(Received::Event(event))) =
+            tokio::time::timeout(Duration::from_millis(50), events.recv()).await
+        {
+            if let BleEvent::DeviceNotification(_, data) = &*event {
+                payloads.push(data.clone());
+            }
+        }
+        payloads
+    }
+
+    #[tokio::test]
+    async fn reconnect_replaces_the_forwarding_task() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe(&[Topic::Data]);
+        let counters = Arc::new(DeviceCounters::default());
+        let mut sessions = Sessions::default();
+
+        let (first, first_dropped, session) = connect_watched(&bus, &counters);
+        sessions.open(session).await;
+        first.unbounded_send(notification(&[1])).unwrap();
+        assert_eq!(drain(&mut events).await, vec![vec![1]]);
+        assert!(!first_dropped.load(Ordering::SeqCst));
+
+        for _ in 0..3 {
+            let (_, session) = connect(&bus, &counters);
+            sessions.open(session).await;
+        }
+        let (current, current_dropped, session) = connect_watched(&bus, &counters);
+        sessions.open(session).await;
+
+        // The first stream never ended, so only the abort can have dropped
+        // it, and `open` waited for that before returning.
+        assert!(first_dropped.load(Ordering::SeqCst));
+        assert!(first.unbounded_send(notification(&[2])).is_err());
+        assert!(!current_dropped.load(Ordering::SeqCst));
+
+        current.unbounded_send(notification(&[3])).unwrap();
+        assert_eq!(drain(&mut events).await, vec![vec![3]]);
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.contains(DEVICE));
+        assert_eq!(counters.notifications.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn disconnect_cancels_the_session() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe(&[Topic::Data]);
+        let counters = Arc::new(DeviceCounters::default());
+        let mut sessions = Sessions::default();
+
+        let (tx, dropped, session) = connect_watched(&bus, &counters);
+        sessions.open(session).await;
+        assert!(sessions.close(DEVICE).await);
+
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(tx.is_closed());
+        assert!(sessions.is_empty());
+        assert!(!sessions.close(DEVICE).await);
+        assert!(drain(&mut events).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn close_cancels_added_tasks() {
+        let bus = EventBus::new();
+        let counters = Arc::new(DeviceCounters::default());
+        let (done_tx, done_rx) = futures::channel::oneshot::channel::<()>();
+        let poll = tokio::spawn(async move {
+            let _done = done_tx;
+            futures::future::pending::<()>().await;
+        });
+
+        let (_tx, session) = connect(&bus, &counters);
+        let session = session.with_tasks(vec![poll]);
+        session.close().await;
+
+        // The sender was dropped with the aborted task, not sent on.
+        assert!(done_rx.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn session_ends_with_its_stream() {
+        let bus = EventBus::new();
+        let counters = Arc::new(DeviceCounters::default());
+
+        let (tx, session) = connect(&bus, &counters);
+        assert!(session.is_active());
+        drop(tx);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!session.is_active());
+        session.close().await;
+    }
+}
@@ -0,0 +1,728 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::advertisement::{AdvertisementConfig, AdvertisementSource};
+use crate::channel::{ChannelConfig, ChannelMode, PRIMARY_TAG};
+use crate::crc::{BadFramePolicy, CrcConfig, CrcWidth};
+use crate::decoder::{DecodedOutput, DecoderConfig};
+use crate::framing::Framing;
+use crate::inspector::CounterConfig;
+use crate::poll::PollConfig;
+use crate::profile::{self, Profile, ProfileRegistry};
+use crate::write_queue::WriteConfig;
+
+pub const SETTINGS_VERSION: u32 = 1;
+
+const APP_IDENTIFIER: &str = "com.bs.ble2serial";
+const SETTINGS_FILE: &str = "settings.json";
+const SETTINGS_TOML_FILE: &str = "settings.toml";
+
+const SERVICE_UUID: Uuid = Uuid::from_u128(0x0000ffa0_0000_1000_8000_00805f9b34fb);
+const CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x0000ffa1_0000_1000_8000_00805f9b34fb);
+/// Name of the profile built from the service and characteristic settings.
+const DEFAULT_PROFILE: &str = "Default";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct BleSettings {
+    pub service_uuid: Uuid,
+    pub characteristic_uuid: Uuid,
+    /// Only devices whose advertised name contains this are listed.
+    pub name_filter: String,
+    /// Devices not heard from for this long are dropped from the scan list.
+    pub device_timeout_secs: u64,
+    /// Stream advertisement payloads instead of connecting.
+    pub advertisement: Option<AdvertisementConfig>,
+    /// Tried after the service and characteristic above, before the built-in ones.
+    pub profiles: Vec<Profile>,
+    /// Battery level in percent below which the UI warns.
+    pub low_battery_percent: u8,
+    /// Characteristics subscribed to next to the profile's one.
+    pub channels: Vec<ChannelConfig>,
+    pub channel_mode: ChannelMode,
+    /// How data written to devices is chunked and paced.
+    pub write: WriteConfig,
+}
+
+impl BleSettings {
+    /// The service and characteristic used for devices without their own.
+    pub fn profile(&self) -> UuidProfile {
+        UuidProfile {
+            service_uuid: self.service_uuid,
+            characteristic_uuid: self.characteristic_uuid,
+        }
+    }
+
+    /// Profiles in detection order: the configured service, the user's
+    /// profiles, then the built-in ones.
+    pub fn registry(&self) -> ProfileRegistry {
+        let configured = Profile {
+            name: DEFAULT_PROFILE.to_string(),
+            ..self.profile().into_profile()
+        };
+        let mut profiles = vec![configured];
+        profiles.extend(self.profiles.iter().cloned());
+        ProfileRegistry::new(&profiles)
+    }
+}
+
+impl Default for BleSettings {
+    fn default() -> Self {
+        Self {
+            service_uuid: SERVICE_UUID,
+            characteristic_uuid: CHARACTERISTIC_UUID,
+            name_filter: "BioSignal".to_string(),
+            device_timeout_secs: 30,
+            advertisement: None,
+            profiles: Vec::new(),
+            low_battery_percent: 20,
+            channels: Vec::new(),
+            channel_mode: ChannelMode::default(),
+            write: WriteConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PortSettings {
+    /// com0com pair carrying the raw stream.
+    pub pair: u32,
+    /// com0com pair used for decoded output.
+    pub decoded_pair: u32,
+    pub baud_rate: u32,
+    /// Name given to the application side of the raw pair, e.g. `COM7`.
+    /// com0com picks a free COM name when unset.
+    pub other_port_name: Option<String>,
+    /// Uninstall the pairs the bridge opened when the app exits.
+    pub remove_on_exit: bool,
+}
+
+impl Default for PortSettings {
+    fn default() -> Self {
+        Self {
+            pair: 0,
+            decoded_pair: 1,
+            baud_rate: 115200,
+            other_port_name: None,
+            remove_on_exit: false,
+        }
+    }
+}
+
+/// Local JSON-RPC control socket. Changes apply on the next start.
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RpcSettings {
+    pub enabled: bool,
+    /// Unix socket path, or pipe name on Windows. See [`crate::rpc::default_path`].
+    pub path: Option<String>,
+}
+
+/// Local HTTP API. Changes apply on the next start.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub enabled: bool,
+    /// Loopback address to listen on; the API has no authentication.
+    pub address: String,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:8787".to_string(),
+        }
+    }
+}
+
+/// Service and characteristic carrying a device's data.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UuidProfile {
+    pub service_uuid: Uuid,
+    pub characteristic_uuid: Uuid,
+}
+
+impl UuidProfile {
+    /// A profile that uses the settings' framing and can't be written to.
+    pub fn into_profile(self) -> Profile {
+        Profile {
+            name: "Custom".to_string(),
+            service_uuid: self.service_uuid,
+            notify_uuid: self.characteristic_uuid,
+            write_uuid: None,
+            framing: None,
+            poll: None,
+        }
+    }
+}
+
+/// A device connected before, remembered across launches, with what users
+/// set for it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct KnownDevice {
+    pub address: String,
+    /// Shown instead of the advertised name.
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// CSS color, e.g. `#3f2aff`.
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// com0com pair the device was last bridged to.
+    #[serde(default)]
+    pub pair: Option<u32>,
+    /// Application side of that pair, e.g. `COM7`.
+    #[serde(default)]
+    pub port: Option<String>,
+    /// Overrides the service and characteristic of the BLE settings.
+    #[serde(default)]
+    pub profile: Option<UuidProfile>,
+    /// Serial port that receives this device's data instead of the bridged
+    /// port while it's connected.
+    #[serde(default)]
+    pub port_name: Option<String>,
+    /// Overrides the framing of the settings.
+    #[serde(default)]
+    pub framing: Option<Framing>,
+    /// Overrides what the CRC settings do with frames failing their checksum.
+    #[serde(default)]
+    pub crc_policy: Option<BadFramePolicy>,
+    /// Decodes this device's frames instead of the decoder of the settings.
+    #[serde(default)]
+    pub decoder: Option<DecoderConfig>,
+    /// Connect as soon as the device is seen after launch.
+    #[serde(default)]
+    pub auto_connect: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub ble: BleSettings,
+    pub port: PortSettings,
+    /// Overrides the bundled com0com directory.
+    pub resource_path: Option<PathBuf>,
+    pub counter: Option<CounterConfig>,
+    pub framing: Framing,
+    pub crc: Option<CrcConfig>,
+    pub decoder: Option<DecoderConfig>,
+    pub rpc: RpcSettings,
+    pub http: HttpSettings,
+    pub known_devices: Vec<KnownDevice>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            ble: Default::default(),
+            port: Default::default(),
+            resource_path: None,
+            counter: None,
+            framing: Default::default(),
+            crc: None,
+            decoder: None,
+            rpc: Default::default(),
+            http: Default::default(),
+            known_devices: Vec::new(),
+        }
+    }
+}
+
+/// A validation problem with a single settings field.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FieldError {
+    /// Dotted path of the field, e.g. `port.baud_rate`.
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// The settings file was written by a newer version of the app.
+#[derive(Debug)]
+pub struct NewerVersion(pub u32);
+
+impl fmt::Display for NewerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Settings version {} is newer than this app", self.0)
+    }
+}
+
+impl Error for NewerVersion {}
+
+/// `settings.toml` in the application config directory when it exists,
+/// `settings.json` otherwise.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = tauri::api::path::config_dir()?.join(APP_IDENTIFIER);
+    let toml = dir.join(SETTINGS_TOML_FILE);
+    if toml.exists() {
+        return Some(toml);
+    }
+    Some(dir.join(SETTINGS_FILE))
+}
+
+/// Whether `path` is read and written as TOML rather than JSON.
+fn is_toml(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "toml")
+}
+
+/// Whether `address` looks like `AA:BB:CC:DD:EE:FF`.
+fn is_address(address: &str) -> bool {
+    let parts: Vec<&str> = address.split(':').collect();
+    parts.len() == 6
+        && parts
+            .iter()
+            .all(|part| part.len() == 2 && u8::from_str_radix(part, 16).is_ok())
+}
+
+fn check_poll(errors: &mut Vec<FieldError>, field: &str, poll: Option<&PollConfig>) {
+    if poll.map_or(false, |poll| poll.interval_ms == 0) {
+        errors.push(FieldError::new(
+            &format!("{}.interval_ms", field),
+            "must be positive",
+        ));
+    }
+}
+
+fn check_width(errors: &mut Vec<FieldError>, field: &str, width: usize) {
+    if !(1..=4).contains(&width) {
+        errors.push(FieldError::new(field, "must be between 1 and 4 bytes"));
+    }
+}
+
+fn check_decoder(errors: &mut Vec<FieldError>, field: &str, decoder: &DecoderConfig) {
+    if decoder.channels == 0 {
+        errors.push(FieldError::new(
+            &format!("{}.channels", field),
+            "must be positive",
+        ));
+    }
+    check_width(
+        errors,
+        &format!("{}.sample_width", field),
+        decoder.sample_width,
+    );
+    check_width(
+        errors,
+        &format!("{}.counter_width", field),
+        decoder.counter_width,
+    );
+}
+
+fn check_framing(errors: &mut Vec<FieldError>, field: &str, framing: &Framing) {
+    match framing {
+        Framing::LengthPrefixed(prefix) => {
+            check_width(errors, &format!("{}.width", field), prefix.width)
+        }
+        Framing::Delimiter(delimiter) if delimiter.is_empty() => errors.push(FieldError::new(
+            &format!("{}.delimiter", field),
+            "must not be empty",
+        )),
+        _ => {}
+    }
+}
+
+impl Settings {
+    pub fn known_device(&self, address: &str) -> Option<&KnownDevice> {
+        self.known_devices
+            .iter()
+            .find(|known| known.address.eq_ignore_ascii_case(address))
+    }
+
+    pub fn known_device_mut(&mut self, address: &str) -> Option<&mut KnownDevice> {
+        self.known_devices
+            .iter_mut()
+            .find(|known| known.address.eq_ignore_ascii_case(address))
+    }
+
+    /// Reads and migrates the settings file, TOML when it ends in `.toml` and
+    /// JSON otherwise; defaults when it doesn't exist yet.
+    ///
+    /// Fails with [`NewerVersion`] for files of a newer app.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(path)?;
+        let value: Value = if is_toml(path) {
+            toml::from_str(&text)?
+        } else {
+            serde_json::from_str(&text)?
+        };
+        let settings: Settings = serde_json::from_value(migrate(value)?)?;
+        if let Err(errors) = settings.validate() {
+            let errors = errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(format!("Invalid settings in {}: {}", path.display(), errors).into());
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let (text, tmp_extension) = if is_toml(path) {
+            (toml::to_string_pretty(self)?, "toml.tmp")
+        } else {
+            (serde_json::to_string_pretty(self)?, "json.tmp")
+        };
+        // Write next to the file first so a crash never leaves it half written.
+        let tmp = path.with_extension(tmp_extension);
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Renames a settings file that can't be loaded to `<name>.bad`, so saving
+    /// the defaults doesn't overwrite it.
+    pub fn set_aside(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let mut name = path
+            .file_name()
+            .ok_or("Settings path has no file name")?
+            .to_os_string();
+        name.push(".bad");
+        let bad = path.with_file_name(name);
+        fs::rename(path, &bad)?;
+        Ok(bad)
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        if self.version != SETTINGS_VERSION {
+            errors.push(FieldError::new(
+                "version",
+                format!("expected {}", SETTINGS_VERSION),
+            ));
+        }
+        if self.ble.service_uuid.is_nil() {
+            errors.push(FieldError::new("ble.service_uuid", "must not be nil"));
+        }
+        if self.ble.characteristic_uuid.is_nil() {
+            errors.push(FieldError::new(
+                "ble.characteristic_uuid",
+                "must not be nil",
+            ));
+        }
+        if self.ble.device_timeout_secs == 0 {
+            errors.push(FieldError::new(
+                "ble.device_timeout_secs",
+                "must be positive",
+            ));
+        }
+        for (i, channel) in self.ble.channels.iter().enumerate() {
+            let field = |name: &str| format!("ble.channels.{}.{}", i, name);
+            let others = &self.ble.channels[..i];
+            if channel.name.is_empty() {
+                errors.push(FieldError::new(&field("name"), "must not be empty"));
+            } else if others.iter().any(|other| other.name == channel.name) {
+                errors.push(FieldError::new(&field("name"), "must be unique"));
+            }
+            if channel.tag == PRIMARY_TAG {
+                errors.push(FieldError::new(
+                    &field("tag"),
+                    "is reserved for the profile's characteristic",
+                ));
+            } else if others.iter().any(|other| other.tag == channel.tag) {
+                errors.push(FieldError::new(&field("tag"), "must be unique"));
+            }
+            if channel.service_uuid.is_nil() || channel.characteristic_uuid.is_nil() {
+                errors.push(FieldError::new(
+                    &field("characteristic_uuid"),
+                    "service and characteristic UUIDs must not be nil",
+                ));
+            }
+            check_poll(&mut errors, &field("poll"), channel.poll.as_ref());
+        }
+        if !(23..=517).contains(&self.ble.write.att_mtu) {
+            errors.push(FieldError::new(
+                "ble.write.att_mtu",
+                "must be between 23 and 517",
+            ));
+        }
+        if self.ble.write.queue_capacity == 0 {
+            errors.push(FieldError::new(
+                "ble.write.queue_capacity",
+                "must be positive",
+            ));
+        }
+        if self.ble.low_battery_percent > 100 {
+            errors.push(FieldError::new(
+                "ble.low_battery_percent",
+                "must be at most 100",
+            ));
+        }
+        let builtin = profile::builtin();
+        for (i, profile) in self.ble.profiles.iter().enumerate() {
+            let field = |name: &str| format!("ble.profiles.{}.{}", i, name);
+            if profile.name.is_empty() {
+                errors.push(FieldError::new(&field("name"), "must not be empty"));
+            } else if profile.name == DEFAULT_PROFILE
+                || builtin.iter().any(|other| other.name == profile.name)
+            {
+                errors.push(FieldError::new(&field("name"), "is reserved"));
+            } else if self.ble.profiles[..i]
+                .iter()
+                .any(|other| other.name == profile.name)
+            {
+                errors.push(FieldError::new(&field("name"), "must be unique"));
+            }
+            if profile.service_uuid.is_nil() || profile.notify_uuid.is_nil() {
+                errors.push(FieldError::new(
+                    &field("notify_uuid"),
+                    "service and notify UUIDs must not be nil",
+                ));
+            }
+            if let Some(framing) = &profile.framing {
+                check_framing(&mut errors, &field("framing"), framing);
+            }
+            check_poll(&mut errors, &field("poll"), profile.poll.as_ref());
+        }
+        if let Some(advertisement) = &self.ble.advertisement {
+            if advertisement.source == AdvertisementSource::ServiceData
+                && advertisement.company_id.is_some()
+            {
+                errors.push(FieldError::new(
+                    "ble.advertisement.company_id",
+                    "only applies to manufacturer data",
+                ));
+            }
+            if advertisement.source == AdvertisementSource::ManufacturerData
+                && advertisement.service_uuid.is_some()
+            {
+                errors.push(FieldError::new(
+                    "ble.advertisement.service_uuid",
+                    "only applies to service data",
+                ));
+            }
+        }
+        if self.port.baud_rate == 0 {
+            errors.push(FieldError::new("port.baud_rate", "must be positive"));
+        }
+        if self.port.pair == self.port.decoded_pair {
+            errors.push(FieldError::new(
+                "port.decoded_pair",
+                "must differ from port.pair",
+            ));
+        }
+        if let Some(name) = &self.port.other_port_name {
+            if !name.starts_with("COM") || name[3..].parse::<u32>().is_err() {
+                errors.push(FieldError::new(
+                    "port.other_port_name",
+                    "must look like COM<number>",
+                ));
+            }
+        }
+
+        if let Some(counter) = &self.counter {
+            check_width(&mut errors, "counter.width", counter.width);
+        }
+
+        check_framing(&mut errors, "framing", &self.framing);
+
+        if let Some(crc) = &self.crc {
+            let max = match crc.width {
+                CrcWidth::Crc8 => u8::MAX as u32,
+                CrcWidth::Crc16 => u16::MAX as u32,
+                CrcWidth::Crc32 => u32::MAX,
+            };
+            if crc.polynomial == 0 || crc.polynomial > max {
+                errors.push(FieldError::new(
+                    "crc.polynomial",
+                    "must be non-zero and fit the CRC width",
+                ));
+            }
+        }
+
+        if let Some(decoder) = &self.decoder {
+            check_decoder(&mut errors, "decoder", decoder);
+        }
+
+        if let Some(path) = &self.rpc.path {
+            if path.is_empty() {
+                errors.push(FieldError::new("rpc.path", "must not be empty"));
+            }
+        }
+
+        match self.http.address.parse::<SocketAddr>() {
+            // The API has no authentication, so it must not be reachable
+            // from other hosts.
+            Ok(address) if !address.ip().is_loopback() => errors.push(FieldError::new(
+                "http.address",
+                "must be a loopback address",
+            )),
+            Ok(_) => {}
+            Err(_) => errors.push(FieldError::new(
+                "http.address",
+                "must look like 127.0.0.1:8787",
+            )),
+        }
+
+        for (i, known) in self.known_devices.iter().enumerate() {
+            let field = |name: &str| format!("known_devices.{}.{}", i, name);
+            if !is_address(&known.address) {
+                errors.push(FieldError::new(
+                    &field("address"),
+                    "must look like AA:BB:CC:DD:EE:FF",
+                ));
+            }
+            if let Some(profile) = &known.profile {
+                if profile.service_uuid.is_nil() || profile.characteristic_uuid.is_nil() {
+                    errors.push(FieldError::new(&field("profile"), "must not be nil"));
+                }
+            }
+            if let Some(name) = &known.port_name {
+                if name.is_empty() {
+                    errors.push(FieldError::new(&field("port_name"), "must not be empty"));
+                }
+            }
+            if let Some(framing) = &known.framing {
+                check_framing(&mut errors, &field("framing"), framing);
+            }
+            if let Some(decoder) = &known.decoder {
+                check_decoder(&mut errors, &field("decoder"), decoder);
+                if decoder.output == DecodedOutput::Port {
+                    // The decoded pair belongs to the decoder of the settings.
+                    errors.push(FieldError::new(&field("decoder.output"), "must be a file"));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Upgrades a settings document by one version.
+type Migration = fn(Value) -> Result<Value, Box<dyn Error>>;
+
+/// `MIGRATIONS[i]` upgrades documents of version `i + 1`; add one for every
+/// version bump.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Brings a settings document written by an older version up to
+/// [`SETTINGS_VERSION`], one version at a time. Documents without a version
+/// are taken to be current.
+fn migrate(mut value: Value) -> Result<Value, Box<dyn Error>> {
+    let mut version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .map_or(SETTINGS_VERSION, |version| version as u32);
+    if version > SETTINGS_VERSION {
+        return Err(NewerVersion(version).into());
+    }
+
+    while version < SETTINGS_VERSION {
+        let step = version
+            .checked_sub(1)
+            .and_then(|index| MIGRATIONS.get(index as usize))
+            .ok_or_else(|| format!("Settings version {} can't be migrated", version))?;
+        value = step(value)?;
+        version += 1;
+        value["version"] = version.into();
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::framing::LengthPrefix;
+    use crate::inspector::Endianness;
+
+    #[test]
+    fn current_version_is_not_migrated() {
+        let value = json!({ "version": SETTINGS_VERSION, "framing": "Slip" });
+        assert_eq!(migrate(value.clone()).unwrap(), value);
+    }
+
+    #[test]
+    fn missing_version_is_taken_as_current() {
+        let settings: Settings = serde_json::from_value(migrate(json!({})).unwrap()).unwrap();
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let err = migrate(json!({ "version": SETTINGS_VERSION + 1 })).unwrap_err();
+        assert!(err.is::<NewerVersion>());
+    }
+
+    #[test]
+    fn version_without_migration_is_an_error() {
+        let err = migrate(json!({ "version": 0 })).unwrap_err();
+        assert!(!err.is::<NewerVersion>());
+    }
+
+    #[test]
+    fn zero_width_length_prefix_is_rejected() {
+        let mut settings = Settings::default();
+        settings.framing = Framing::LengthPrefixed(LengthPrefix {
+            offset: 0,
+            width: 0,
+            endianness: Endianness::Little,
+            includes_header: false,
+        });
+
+        let errors = settings.validate().unwrap_err();
+        assert!(errors.iter().any(|error| error.field == "framing.width"));
+    }
+
+    #[test]
+    fn http_address_must_be_loopback() {
+        let mut settings = Settings::default();
+        for address in ["127.0.0.1:8787", "[::1]:8787"] {
+            settings.http.address = address.to_string();
+            assert!(settings.validate().is_ok(), "{}", address);
+        }
+        for address in [
+            "0.0.0.0:8787",
+            "192.168.1.10:8787",
+            "[::]:8787",
+            "localhost",
+        ] {
+            settings.http.address = address.to_string();
+            let errors = settings.validate().unwrap_err();
+            assert!(
+                errors.iter().any(|error| error.field == "http.address"),
+                "{}",
+                address
+            );
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, error};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+use crate::decoder::{DecoderConfig, FrameDecoder};
+use crate::framing::Frame;
+
+/// Items a sink may queue before new data is dropped for it.
+const SINK_BUFFER: usize = 256;
+
+/// How long a write to a TCP sink client may take before it is disconnected.
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum SinkData {
+    /// Bytes inserted by the bridge itself, such as gap or CRC markers.
+    Marker {
+        id: String,
+        bytes: Vec<u8>,
+    },
+    Frame {
+        id: String,
+        frame: Frame,
+    },
+    /// Data of a separate channel, only written to that channel's sinks.
+    Channel {
+        id: String,
+        channel: String,
+        bytes: Vec<u8>,
+    },
+}
+
+impl SinkData {
+    fn id(&self) -> &str {
+        match self {
+            SinkData::Marker { id, .. }
+            | SinkData::Frame { id, .. }
+            | SinkData::Channel { id, .. } => id,
+        }
+    }
+
+    fn channel(&self) -> Option<&str> {
+        match self {
+            SinkData::Channel { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
+    fn raw(&self) -> &[u8] {
+        match self {
+            SinkData::Marker { bytes, .. } => bytes,
+            SinkData::Frame { frame, .. } => &frame.raw,
+            SinkData::Channel { bytes, .. } => bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum SinkConfig {
+    /// Raw bytes to the A side of com0com pair `index`.
+    Port {
+        index: u32,
+    },
+    /// Raw bytes to an existing serial port, e.g. `/dev/ttyUSB0` or `COM3`.
+    Serial(String),
+    /// Raw bytes appended to a recording file.
+    File(PathBuf),
+    /// Raw bytes to every client connected to this listen address.
+    Tcp(String),
+    Decoded(DecoderConfig),
+}
+
+impl SinkConfig {
+    /// Whether a device's own sink of this kind stands in for a shared
+    /// `other` one: its own port for the bridged port, its own decoder for
+    /// the shared decoder.
+    fn replaces(&self, other: &SinkConfig) -> bool {
+        matches!(
+            (self, other),
+            (
+                SinkConfig::Port { .. } | SinkConfig::Serial(_),
+                SinkConfig::Port { .. } | SinkConfig::Serial(_)
+            ) | (SinkConfig::Decoded(_), SinkConfig::Decoded(_))
+        )
+    }
+}
+
+/// A blocking output, fed from its own thread.
+pub trait Sink: Send + 'static {
+    fn write(&mut self, data: &SinkData) -> Result<(), Box<dyn Error>>;
+}
+
+pub struct RawSink(pub Box<dyn Write + Send>);
+
+impl Sink for RawSink {
+    fn write(&mut self, data: &SinkData) -> Result<(), Box<dyn Error>> {
+        self.0.write_all(data.raw())?;
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+impl Sink for FrameDecoder {
+    fn write(&mut self, data: &SinkData) -> Result<(), Box<dyn Error>> {
+        if let SinkData::Frame { id, frame } = data {
+            self.write_frame(id, &frame.payload)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct SinkCounters {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    errors: AtomicU64,
+    /// Write errors by the device whose data failed to be written.
+    device_errors: std::sync::Mutex<HashMap<String, u64>>,
+}
+
+impl SinkCounters {
+    fn write_failed(&self, id: &str) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut device_errors) = self.device_errors.lock() {
+            *device_errors.entry(id.to_string()).or_default() += 1;
+        }
+    }
+}
+
+pub struct SinkHandle {
+    tx: mpsc::Sender<Arc<SinkData>>,
+    counters: Arc<SinkCounters>,
+    task: JoinHandle<()>,
+}
+
+impl SinkHandle {
+    /// Stops accepting data and waits until the sink has written what it has
+    /// queued and released its output.
+    pub async fn close(self) {
+        drop(self.tx);
+        if let Err(err) = self.task.await {
+            error!("Sink task failed: {}", err);
+        }
+    }
+}
+
+pub fn spawn_sink(mut sink: Box<dyn Sink>) -> SinkHandle {
+    let (tx, mut rx) = mpsc::channel::<Arc<SinkData>>(SINK_BUFFER);
+    let counters = Arc::new(SinkCounters::default());
+    let task_counters = counters.clone();
+
+    let task = tokio::task::spawn_blocking(move || {
+        while let Some(data) = rx.blocking_recv() {
+            match sink.write(&data) {
+                Ok(()) => {
+                    task_counters.written.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => {
+                    task_counters.write_failed(data.id());
+                    error!("Error writing to sink: {}", err);
+                }
+            }
+        }
+    });
+
+    SinkHandle { tx, counters, task }
+}
+
+/// Binds `address` and returns the sink together with the bound address.
+///
+/// Each client is written to by its own task, so a stalled client can't hold
+/// up the others; one that falls [`SINK_BUFFER`] items behind or takes longer
+/// than [`TCP_WRITE_TIMEOUT`] for a write is disconnected.
+pub async fn spawn_tcp_sink(address: &str) -> Result<(SinkHandle, String), Box<dyn Error>> {
+    let listener = TcpListener::bind(address).await?;
+    let local_address = listener.local_addr()?.to_string();
+
+    let (tx, mut rx) = mpsc::channel::<Arc<SinkData>>(SINK_BUFFER);
+    let counters = Arc::new(SinkCounters::default());
+    let task_counters = counters.clone();
+
+    let task = tokio::spawn(async move {
+        let mut clients: Vec<mpsc::Sender<Arc<SinkData>>> = Vec::new();
+        let mut client_tasks = Vec::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        debug!("TCP sink client connected: {}", peer);
+                        let (client_tx, client_rx) = mpsc::channel(SINK_BUFFER);
+                        clients.push(client_tx);
+                        client_tasks.push(tokio::spawn(write_tcp_client(
+                            stream,
+                            client_rx,
+                            task_counters.clone(),
+                        )));
+                    }
+                    Err(err) => error!("Error accepting TCP sink client: {}", err),
+                },
+                data = rx.recv() => {
+                    let data = match data {
+                        Some(data) => data,
+                        None => break,
+                    };
+                    clients.retain(|client| match client.try_send(data.clone()) {
+                        Ok(()) => true,
+                        Err(TrySendError::Full(_)) => {
+                            task_counters.dropped.fetch_add(1, Ordering::Relaxed);
+                            debug!("TCP sink client fell behind, disconnecting it");
+                            false
+                        }
+                        Err(TrySendError::Closed(_)) => false,
+                    });
+                    task_counters.written.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        // Let the clients write what they have queued.
+        drop(clients);
+        for client_task in client_tasks {
+            let _ = client_task.await;
+        }
+    });
+
+    Ok((SinkHandle { tx, counters, task }, local_address))
+}
+
+async fn write_tcp_client(
+    mut stream: TcpStream,
+    mut rx: mpsc::Receiver<Arc<SinkData>>,
+    counters: Arc<SinkCounters>,
+) {
+    while let Some(data) = rx.recv().await {
+        let result = tokio::time::timeout(TCP_WRITE_TIMEOUT, stream.write_all(data.raw())).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+                debug!("TCP sink client dropped: {}", err);
+                return;
+            }
+            Err(_) => {
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+                debug!("TCP sink client timed out");
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SinkInfo {
+    pub name: String,
+    /// Only this device's data reaches the sink when set.
+    pub device: Option<String>,
+    /// The separate channel the sink writes; the bridged data when unset.
+    #[serde(default)]
+    pub channel: Option<String>,
+    pub config: SinkConfig,
+    /// Port name, file path or bound address the data ends up at.
+    pub location: Option<String>,
+    pub written: u64,
+    pub dropped: u64,
+    pub errors: u64,
+    pub closed: bool,
+}
+
+struct RegisteredSink {
+    info: SinkInfo,
+    handle: SinkHandle,
+}
+
+impl RegisteredSink {
+    fn info(&self) -> SinkInfo {
+        let counters = &self.handle.counters;
+        SinkInfo {
+            written: counters.written.load(Ordering::Relaxed),
+            dropped: counters.dropped.load(Ordering::Relaxed),
+            errors: counters.errors.load(Ordering::Relaxed),
+            closed: self.handle.tx.is_closed(),
+            ..self.info.clone()
+        }
+    }
+}
+
+/// Every output the notification stream is fanned out to.
+#[derive(Default)]
+pub struct SinkRegistry {
+    sinks: HashMap<String, RegisteredSink>,
+}
+
+impl SinkRegistry {
+    pub fn insert(
+        &mut self,
+        name: String,
+        device: Option<String>,
+        channel: Option<String>,
+        config: SinkConfig,
+        location: Option<String>,
+        handle: SinkHandle,
+    ) -> SinkInfo {
+        let sink = RegisteredSink {
+            info: SinkInfo {
+                name: name.clone(),
+                device,
+                channel,
+                config,
+                location,
+                written: 0,
+                dropped: 0,
+                errors: 0,
+                closed: false,
+            },
+            handle,
+        };
+        let info = sink.info();
+        self.sinks.insert(name, sink);
+        info
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<SinkHandle> {
+        self.sinks.remove(name).map(|sink| sink.handle)
+    }
+
+    /// Whether `id` has a sink of its own that replaces the shared `sink`.
+    fn replaced(&self, id: &str, sink: &SinkInfo) -> bool {
+        self.sinks.values().any(|own| {
+            own.info.device.as_deref() == Some(id)
+                && own.info.channel == sink.channel
+                && own.info.config.replaces(&sink.config)
+        })
+    }
+
+    /// Queues `data` on every matching sink without waiting; sinks with a full
+    /// buffer drop it.
+    pub fn send(&self, data: SinkData) {
+        let data = Arc::new(data);
+        for sink in self.sinks.values() {
+            match &sink.info.device {
+                Some(device) if device != data.id() => continue,
+                None if self.replaced(data.id(), &sink.info) => continue,
+                _ => {}
+            }
+            if sink.info.channel.as_deref() != data.channel() {
+                continue;
+            }
+            match sink.handle.tx.try_send(data.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    sink.handle.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+    }
+
+    pub fn list(&self) -> Vec<SinkInfo> {
+        self.sinks.values().map(|sink| sink.info()).collect()
+    }
+
+    /// Write errors of the serial and com0com port sinks by device.
+    pub fn port_errors(&self) -> HashMap<String, u64> {
+        let mut errors = HashMap::new();
+        for sink in self.sinks.values() {
+            if !matches!(
+                sink.info.config,
+                SinkConfig::Port { .. } | SinkConfig::Serial(_)
+            ) {
+                continue;
+            }
+            if let Ok(device_errors) = sink.handle.counters.device_errors.lock() {
+                for (id, count) in device_errors.iter() {
+                    *errors.entry(id.clone()).or_default() += count;
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+    const OTHER: &str = "11:22:33:44:55:66";
+
+    /// Collects the devices whose data reached the sink.
+    struct Devices(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl Sink for Devices {
+        fn write(&mut self, data: &SinkData) -> Result<(), Box<dyn Error>> {
+            self.0.lock().unwrap().push(data.id().to_string());
+            Ok(())
+        }
+    }
+
+    fn insert(
+        registry: &mut SinkRegistry,
+        name: &str,
+        device: Option<&str>,
+        config: SinkConfig,
+    ) -> Arc<std::sync::Mutex<Vec<String>>> {
+        let devices = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handle = spawn_sink(Box::new(Devices(devices.clone())));
+        registry.insert(
+            name.to_string(),
+            device.map(String::from),
+            None,
+            config,
+            None,
+            handle,
+        );
+        devices
+    }
+
+    fn marker(id: &str) -> SinkData {
+        SinkData::Marker {
+            id: id.to_string(),
+            bytes: vec![0xEE],
+        }
+    }
+
+    #[tokio::test]
+    async fn own_port_replaces_the_shared_one() {
+        let mut registry = SinkRegistry::default();
+        let shared = insert(&mut registry, "port", None, SinkConfig::Port { index: 0 });
+        let own = insert(
+            &mut registry,
+            "device",
+            Some(DEVICE),
+            SinkConfig::Serial("/dev/ttyUSB0".into()),
+        );
+        let recording = insert(&mut registry, "file", None, SinkConfig::File("rec".into()));
+
+        registry.send(marker(DEVICE));
+        registry.send(marker(OTHER));
+        // Once the device's port is closed, the shared port gets its data again.
+        registry.remove("device").unwrap().close().await;
+        registry.send(marker(DEVICE));
+        for name in ["port", "file"] {
+            registry.remove(name).unwrap().close().await;
+        }
+
+        assert_eq!(*shared.lock().unwrap(), vec![OTHER, DEVICE]);
+        assert_eq!(*own.lock().unwrap(), vec![DEVICE]);
+        assert_eq!(*recording.lock().unwrap(), vec![DEVICE, OTHER, DEVICE]);
+    }
+}
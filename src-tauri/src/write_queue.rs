@@ -0,0 +1,428 @@
+use std::error::Error;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use btleplug::api::{CharPropFlags, Characteristic, Peripheral, WriteType};
+use log::{debug, error};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+/// Bytes of each ATT write taken by the opcode and handle.
+const ATT_HEADER_LEN: usize = 3;
+
+/// How data written to a device is split up and paced.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WriteConfig {
+    /// ATT MTU assumed for the connection; chunks are 3 bytes smaller. This
+    /// is configured, not negotiated: btleplug doesn't report what the device
+    /// agreed to, so raise it only for devices known to accept more than the
+    /// minimum of 23.
+    #[serde(alias = "mtu")]
+    pub att_mtu: usize,
+    /// Wait for the device to acknowledge each chunk, even when the
+    /// characteristic accepts writes without response.
+    pub with_response: bool,
+    /// Pause after each chunk so writes without response don't overrun the
+    /// device.
+    pub chunk_delay_ms: u64,
+    /// Attempts after the first before a chunk, and the rest of its write,
+    /// is given up on. Only timed out chunks are retried.
+    pub retries: u32,
+    pub retry_delay_ms: u64,
+    /// Writes that may wait for the device before new ones are refused.
+    pub queue_capacity: usize,
+}
+
+impl Default for WriteConfig {
+    fn default() -> Self {
+        Self {
+            att_mtu: 23,
+            with_response: false,
+            chunk_delay_ms: 10,
+            retries: 3,
+            retry_delay_ms: 50,
+            queue_capacity: 64,
+        }
+    }
+}
+
+impl WriteConfig {
+    pub fn chunk_len(&self) -> usize {
+        self.att_mtu.saturating_sub(ATT_HEADER_LEN).max(1)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WriteCounters {
+    /// Writes queued or being written.
+    pending: AtomicU64,
+    writes: AtomicU64,
+    chunks: AtomicU64,
+    bytes: AtomicU64,
+    retries: AtomicU64,
+    /// Writes given up on after an error that can't be retried or running
+    /// out of retries.
+    failures: AtomicU64,
+    /// Writes refused because the queue was full.
+    rejected: AtomicU64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WriteStats {
+    pub queue_depth: u64,
+    pub writes: u64,
+    pub chunks: u64,
+    pub bytes: u64,
+    pub retries: u64,
+    pub failures: u64,
+    pub rejected: u64,
+}
+
+impl WriteCounters {
+    pub fn stats(&self) -> WriteStats {
+        WriteStats {
+            queue_depth: self.pending.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            chunks: self.chunks.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Writes to one device's characteristic in order, one chunk at a time.
+pub struct WriteQueue {
+    characteristic: Characteristic,
+    tx: mpsc::Sender<Vec<u8>>,
+    counters: Arc<WriteCounters>,
+    /// This queue's share of `counters.pending`, which outlives the queue.
+    pending: Arc<AtomicU64>,
+    task: JoinHandle<()>,
+}
+
+impl WriteQueue {
+    pub fn spawn<P>(
+        peripheral: P,
+        characteristic: Characteristic,
+        config: WriteConfig,
+        counters: Arc<WriteCounters>,
+    ) -> Self
+    where
+        P: Peripheral + 'static,
+    {
+        let write_type = if !config.with_response
+            && characteristic
+                .properties
+                .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+        {
+            WriteType::WithoutResponse
+        } else {
+            WriteType::WithResponse
+        };
+        let target = characteristic.clone();
+        Self::with_writer(characteristic, config, counters, move |chunk| {
+            let peripheral = peripheral.clone();
+            let target = target.clone();
+            async move { peripheral.write(&target, &chunk, write_type).await }
+        })
+    }
+
+    /// A queue handing every chunk to `write_chunk`.
+    fn with_writer<F, Fut>(
+        characteristic: Characteristic,
+        config: WriteConfig,
+        counters: Arc<WriteCounters>,
+        mut write_chunk: F,
+    ) -> Self
+    where
+        F: FnMut(Vec<u8>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), btleplug::Error>> + Send,
+    {
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(config.queue_capacity.max(1));
+        let task_characteristic = characteristic.clone();
+        let task_counters = counters.clone();
+        let pending = Arc::new(AtomicU64::new(0));
+        let task_pending = pending.clone();
+        let task = tokio::spawn(async move {
+            while let Some(data) = rx.recv().await {
+                write(
+                    &mut write_chunk,
+                    &task_characteristic,
+                    &config,
+                    &task_counters,
+                    &data,
+                )
+                .await;
+                task_pending.fetch_sub(1, Ordering::Relaxed);
+                task_counters.pending.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+
+        Self {
+            characteristic,
+            tx,
+            counters,
+            pending,
+            task,
+        }
+    }
+
+    pub fn characteristic(&self) -> &Characteristic {
+        &self.characteristic
+    }
+
+    /// Queues `data` without waiting for it to be written.
+    pub fn push(&self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        self.counters.pending.fetch_add(1, Ordering::Relaxed);
+        let result = self.tx.try_send(data);
+        if result.is_err() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            self.counters.pending.fetch_sub(1, Ordering::Relaxed);
+        }
+        match result {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+                Err("Write queue is full".into())
+            }
+            Err(TrySendError::Closed(_)) => Err("Write queue is closed".into()),
+        }
+    }
+
+    /// Stops writing; queued data is dropped.
+    pub async fn close(self) {
+        self.task.abort();
+        let _ = self.task.await;
+        // Another session of the device may share the counters by now.
+        let dropped = self.pending.swap(0, Ordering::Relaxed);
+        self.counters.pending.fetch_sub(dropped, Ordering::Relaxed);
+    }
+}
+
+/// Whether writing again may succeed. Other errors, like the device being
+/// gone or refusing the write, won't change on a retry, and the backend
+/// errors are too vague to tell.
+fn is_transient(err: &btleplug::Error) -> bool {
+    matches!(err, btleplug::Error::TimedOut(_))
+}
+
+async fn write<F, Fut>(
+    write_chunk: &mut F,
+    characteristic: &Characteristic,
+    config: &WriteConfig,
+    counters: &WriteCounters,
+    data: &[u8],
+) where
+    F: FnMut(Vec<u8>) -> Fut,
+    Fut: Future<Output = Result<(), btleplug::Error>>,
+{
+    let chunk_delay = Duration::from_millis(config.chunk_delay_ms);
+    let retry_delay = Duration::from_millis(config.retry_delay_ms);
+    for chunk in data.chunks(config.chunk_len()) {
+        let mut attempt = 0;
+        loop {
+            match write_chunk(chunk.to_vec()).await {
+                Ok(()) => break,
+                Err(err) if attempt < config.retries && is_transient(&err) => {
+                    attempt += 1;
+                    counters.retries.fetch_add(1, Ordering::Relaxed);
+                    debug!("Retrying write to {}: {}", characteristic.uuid, err);
+                    tokio::time::sleep(retry_delay).await;
+                }
+                Err(err) => {
+                    counters.failures.fetch_add(1, Ordering::Relaxed);
+                    error!("Error writing to {}: {}", characteristic.uuid, err);
+                    return;
+                }
+            }
+        }
+        counters.chunks.fetch_add(1, Ordering::Relaxed);
+        counters
+            .bytes
+            .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        if !chunk_delay.is_zero() {
+            tokio::time::sleep(chunk_delay).await;
+        }
+    }
+    counters.writes.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::future::{self, Ready};
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn config() -> WriteConfig {
+        WriteConfig {
+            chunk_delay_ms: 0,
+            retries: 2,
+            retry_delay_ms: 0,
+            ..WriteConfig::default()
+        }
+    }
+
+    /// Records the chunks written, failing the first attempts with the
+    /// scripted errors.
+    #[derive(Clone, Default)]
+    struct Device {
+        chunks: Arc<Mutex<Vec<Vec<u8>>>>,
+        errors: Arc<Mutex<VecDeque<btleplug::Error>>>,
+    }
+
+    impl Device {
+        fn failing(errors: Vec<btleplug::Error>) -> Self {
+            Self {
+                errors: Arc::new(Mutex::new(errors.into())),
+                ..Self::default()
+            }
+        }
+
+        fn writer(&self) -> impl FnMut(Vec<u8>) -> Ready<Result<(), btleplug::Error>> {
+            let device = self.clone();
+            move |chunk| {
+                let result = match device.errors.lock().unwrap().pop_front() {
+                    Some(err) => Err(err),
+                    None => {
+                        device.chunks.lock().unwrap().push(chunk);
+                        Ok(())
+                    }
+                };
+                future::ready(result)
+            }
+        }
+
+        fn chunk_lens(&self) -> Vec<usize> {
+            self.chunks.lock().unwrap().iter().map(Vec::len).collect()
+        }
+    }
+
+    async fn write_to(device: &Device, config: &WriteConfig, data: &[u8]) -> WriteStats {
+        let counters = WriteCounters::default();
+        write(
+            &mut device.writer(),
+            &Characteristic::default(),
+            config,
+            &counters,
+            data,
+        )
+        .await;
+        counters.stats()
+    }
+
+    #[test]
+    fn chunks_leave_room_for_the_att_header() {
+        assert_eq!(WriteConfig::default().chunk_len(), 20);
+        let config = |att_mtu| WriteConfig {
+            att_mtu,
+            ..WriteConfig::default()
+        };
+        assert_eq!(config(247).chunk_len(), 244);
+        assert_eq!(config(3).chunk_len(), 1);
+        assert_eq!(config(0).chunk_len(), 1);
+    }
+
+    #[tokio::test]
+    async fn data_is_written_in_chunks() {
+        let device = Device::default();
+        let data: Vec<u8> = (0..45).collect();
+        let stats = write_to(&device, &config(), &data).await;
+
+        assert_eq!(device.chunk_lens(), vec![20, 20, 5]);
+        assert_eq!(device.chunks.lock().unwrap().concat(), data);
+        assert_eq!((stats.writes, stats.chunks, stats.bytes), (1, 3, 45));
+        assert_eq!((stats.retries, stats.failures), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn timed_out_chunks_are_retried() {
+        let timeout = || btleplug::Error::TimedOut(Duration::from_secs(1));
+        let device = Device::failing(vec![timeout(), timeout()]);
+        let stats = write_to(&device, &config(), &[1; 30]).await;
+
+        assert_eq!(device.chunk_lens(), vec![20, 10]);
+        assert_eq!((stats.writes, stats.retries, stats.failures), (1, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn write_is_given_up_after_the_last_retry() {
+        let timeout = || btleplug::Error::TimedOut(Duration::from_secs(1));
+        let device = Device::failing(vec![timeout(), timeout(), timeout()]);
+        let stats = write_to(&device, &config(), &[1; 30]).await;
+
+        // The rest of the write is dropped with the failed chunk.
+        assert!(device.chunk_lens().is_empty());
+        assert_eq!((stats.writes, stats.retries, stats.failures), (0, 2, 1));
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_not_retried() {
+        for err in [
+            btleplug::Error::NotConnected,
+            btleplug::Error::RuntimeError("write refused".to_string()),
+        ] {
+            let device = Device::failing(vec![err]);
+            let stats = write_to(&device, &config(), &[1; 10]).await;
+
+            assert!(device.chunk_lens().is_empty());
+            assert_eq!((stats.retries, stats.failures), (0, 1));
+        }
+    }
+
+    #[tokio::test]
+    async fn closing_drops_only_its_own_pending_writes() {
+        let counters = Arc::new(WriteCounters::default());
+        let stuck = || {
+            WriteQueue::with_writer(
+                Characteristic::default(),
+                config(),
+                counters.clone(),
+                |_| future::pending(),
+            )
+        };
+
+        let old = stuck();
+        for _ in 0..3 {
+            old.push(vec![1]).unwrap();
+        }
+        // The device reconnected; both sessions share its counters.
+        let new = stuck();
+        new.push(vec![2]).unwrap();
+        assert_eq!(counters.stats().queue_depth, 4);
+
+        old.close().await;
+        assert_eq!(counters.stats().queue_depth, 1);
+        new.close().await;
+        assert_eq!(counters.stats().queue_depth, 0);
+    }
+
+    #[tokio::test]
+    async fn finished_writes_leave_the_queue() {
+        let counters = Arc::new(WriteCounters::default());
+        let device = Device::default();
+        let queue = WriteQueue::with_writer(
+            Characteristic::default(),
+            config(),
+            counters.clone(),
+            device.writer(),
+        );
+        queue.push(vec![1; 25]).unwrap();
+        queue.push(vec![2; 5]).unwrap();
+
+        while counters.stats().writes < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(counters.stats().queue_depth, 0);
+        assert_eq!(device.chunk_lens(), vec![20, 5, 5]);
+        queue.close().await;
+        assert_eq!(counters.stats().queue_depth, 0);
+    }
+}
@@ -28,6 +28,9 @@ const Device = ({ device }: { device: Device }) => {
                             ? theme.colors.dark[8]
                             : theme.colors.gray[2]
                     }`,
+                    borderLeft: device.color
+                        ? `4px solid ${device.color}`
+                        : undefined,
 
                     "&:hover": {
                         cursor: "pointer",
@@ -45,11 +48,16 @@ const Device = ({ device }: { device: Device }) => {
             }}
         >
             <Text size="md" weight={500}>
-                {device.name}
+                {device.alias ?? device.name}
             </Text>
             <Text size="sm" color="dimmed">
-                {device.id}
+                {device.alias ? `${device.name} · ${device.id}` : device.id}
             </Text>
+            {device.notes && (
+                <Text size="xs" color="dimmed">
+                    {device.notes}
+                </Text>
+            )}
         </Card>
     );
 };
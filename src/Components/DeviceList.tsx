@@ -5,6 +5,10 @@ import { Alert, Flex, ScrollArea, Title } from "@mantine/core";
 import { invoke } from "@tauri-apps/api";
 import { IconAlertCircle } from "@tabler/icons-react";
 
+// Strongest signal first, devices without RSSI last.
+const bySignal = (a: Device, b: Device) =>
+    (b.rssi ?? -Infinity) - (a.rssi ?? -Infinity) || a.name.localeCompare(b.name);
+
 const DeviceList = () => {
     const [devices, setDevices] = useState<Device[]>([]);
 
@@ -13,13 +17,20 @@ const DeviceList = () => {
             console.log("device get", devices);
             setDevices(devices);
         });
-        let unlisten = listen<Array<Device>>("devices", (payload) => {
-            console.log("devices", payload.payload);
-            setDevices(payload.payload);
-        });
+        const upsert = (device: Device) =>
+            setDevices((devices) =>
+                [...devices.filter((d) => d.id !== device.id), device].sort(bySignal)
+            );
+        let unlisteners = [
+            listen<Device>("device-added", (payload) => upsert(payload.payload)),
+            listen<Device>("device-updated", (payload) => upsert(payload.payload)),
+            listen<string>("device-lost", (payload) => {
+                setDevices((devices) => devices.filter((d) => d.id !== payload.payload));
+            }),
+        ];
 
         return () => {
-            unlisten.then((f) => f());
+            unlisteners.forEach((unlisten) => unlisten.then((f) => f()));
         };
     }, []);
     return (
@@ -1,4 +1,16 @@
-import { Box, Button, Card, Flex, Title } from "@mantine/core";
+import {
+    Box,
+    Button,
+    Card,
+    ColorInput,
+    Flex,
+    Switch,
+    Text,
+    Textarea,
+    TextInput,
+    Title,
+} from "@mantine/core";
+import { notifications } from "@mantine/notifications";
 import { invoke } from "@tauri-apps/api";
 import React, { useEffect, useState } from "react";
 import useStore from "../store";
@@ -7,9 +19,17 @@ import { useNavigate } from "react-router-dom";
 import Device from "../Components/Device";
 
 const Connected = () => {
-    const device = useStore((state) => state.device);
+    const [device, setDevice] = useStore((state) => [state.device, state.setDevice]);
     const [loading, setLoading] = useState(false);
     const [port, setPort] = useState<string | undefined>(undefined);
+    const [autoConnect, setAutoConnect] = useState(false);
+    const [known, setKnown] = useState<KnownDevice | undefined>(undefined);
+    const [alias, setAlias] = useState(device?.alias ?? "");
+    const [color, setColor] = useState(device?.color ?? "");
+    const [notes, setNotes] = useState(device?.notes ?? "");
+    const [saving, setSaving] = useState(false);
+    const [battery, setBattery] = useState(device?.info?.battery ?? null);
+    const info = device?.info;
     const navigate = useNavigate();
     async function disconnect() {
         setLoading(true);
@@ -23,8 +43,66 @@ const Connected = () => {
         invoke<string | undefined>("get_other_port").then((port) => {
             setPort(port);
         });
+        invoke<Array<KnownDevice>>("get_known_devices").then((known) => {
+            const entry = known.find((k) => k.address === device?.id);
+            setKnown(entry);
+            setAutoConnect(entry?.auto_connect ?? false);
+        });
     }, []);
 
+    async function toggleAutoConnect(enabled: boolean) {
+        if (device) {
+            try {
+                await invoke("set_auto_connect", { address: device.id, enabled });
+                setAutoConnect(enabled);
+            } catch (e) {
+                console.log("set_auto_connect : ", e);
+            }
+        }
+    }
+
+    async function saveDetails() {
+        if (!device) {
+            return;
+        }
+        // Connected devices are remembered, but the entry may not be there yet.
+        const base: KnownDevice = known ?? {
+            address: device.id,
+            alias: null,
+            color: null,
+            notes: null,
+            pair: null,
+            port: null,
+            profile: null,
+            port_name: null,
+            framing: null,
+            crc_policy: null,
+            decoder: null,
+            auto_connect: autoConnect,
+        };
+        const entry: KnownDevice = {
+            ...base,
+            alias: alias.trim() || null,
+            color: color || null,
+            notes: notes.trim() || null,
+        };
+        setSaving(true);
+        try {
+            await invoke("set_known_device", { device: entry });
+            setKnown(entry);
+            setDevice({ ...device, alias: entry.alias, color: entry.color, notes: entry.notes });
+        } catch (e) {
+            const errors = e as Array<FieldError>;
+            notifications.show({
+                title: "Could not save device",
+                message: errors.map((error) => `${error.field}: ${error.message}`).join("\n"),
+                withBorder: true,
+                color: "red",
+            });
+        }
+        setSaving(false);
+    }
+
     useEffect(() => {
         let unlisten = listen("disconnected", (event) => {
             console.log("disconnected : ", event);
@@ -35,6 +113,29 @@ const Connected = () => {
             unlisten.then((unlisten) => unlisten());
         };
     }, []);
+    useEffect(() => {
+        let unlisten = listen<BatteryLevel>("battery-level", (event) => {
+            if (event.payload.id === device?.id) {
+                setBattery(event.payload.level);
+            }
+        });
+        return () => {
+            unlisten.then((unlisten) => unlisten());
+        };
+    }, []);
+    useEffect(() => {
+        let unlisten = listen<BatteryLevel>("battery-low", (event) => {
+            notifications.show({
+                title: "Low battery",
+                message: `${event.payload.id} is at ${event.payload.level}%`,
+                withBorder: true,
+                color: "orange",
+            });
+        });
+        return () => {
+            unlisten.then((unlisten) => unlisten());
+        };
+    }, []);
     useEffect(() => {
         let unlisten = listen("error", (event) => {
             console.log("error : ", event);
@@ -54,12 +155,53 @@ const Connected = () => {
                 Connected to{" "}
             </Title>
             <Device device={device!} />
+            {info && (
+                <Text size="sm" color="dimmed">
+                    {[info.manufacturer, info.model, info.serial && `S/N ${info.serial}`,
+                        info.firmware_revision && `FW ${info.firmware_revision}`,
+                        info.hardware_revision && `HW ${info.hardware_revision}`]
+                        .filter(Boolean)
+                        .join(" · ")}
+                </Text>
+            )}
+            {battery !== null && <Text size="sm">Battery: {battery}%</Text>}
             <Title  order={4}>
                 Use below port in you app
             </Title>
             <Card mx={"auto"} w={"100%"}>
                 <Title align="center">{port}</Title>
             </Card>
+            {device && (
+                <Flex w={"100%"} direction={"column"} gap={"xs"}>
+                    <TextInput
+                        label="Alias"
+                        placeholder={device?.name}
+                        value={alias}
+                        onChange={(event) => setAlias(event.currentTarget.value)}
+                    />
+                    <ColorInput
+                        label="Color"
+                        format="hex"
+                        value={color}
+                        onChange={setColor}
+                    />
+                    <Textarea
+                        label="Notes"
+                        autosize
+                        minRows={2}
+                        value={notes}
+                        onChange={(event) => setNotes(event.currentTarget.value)}
+                    />
+                    <Button variant="outline" loading={saving} onClick={saveDetails}>
+                        Save
+                    </Button>
+                </Flex>
+            )}
+            <Switch
+                label="Connect automatically on launch"
+                checked={autoConnect}
+                onChange={(event) => toggleAutoConnect(event.currentTarget.checked)}
+            />
             <Box w={"100%"} mx={"auto"}>
                 <Button
                     fullWidth
@@ -2,7 +2,7 @@ import { useEffect, useState } from "react";
 import reactLogo from "./assets/react.svg";
 import { invoke } from "@tauri-apps/api/tauri";
 import { emit, listen } from "@tauri-apps/api/event";
-import { Button, Flex, Title, Text, Card, Paper, Alert } from "@mantine/core";
+import { Button, Flex, Title, Text, Card, Paper, Alert, TextInput } from "@mantine/core";
 import DeviceList from "../Components/DeviceList";
 import useStore from "../store";
 import { useNavigate } from "react-router-dom";
@@ -11,7 +11,8 @@ import { IconAlertCircle, IconExclamationCircle } from "@tabler/icons-react";
 import { trace, info, error, attachConsole } from "tauri-plugin-log-api";
 
 function Home() {
-    const device = useStore((state) => state.device);
+    const [device, setDevice] = useStore((state) => [state.device, state.setDevice]);
+    const [address, setAddress] = useState("");
     const [loading, setLoading] = useState(false);
     const [bleon, setBleon] = useState(true);
     const [retryLoading, setRetryLoading] = useState(false);
@@ -50,13 +51,31 @@ function Home() {
         }
     }
 
+    // Connects without waiting for the device to show up in the list.
+    async function connect_address() {
+        setLoading(true);
+        try {
+            await invoke("connect_address", { address: address.trim() });
+        } catch (e) {
+            error(e as string);
+            setLoading(false);
+            notifications.show({
+                title: "Connection Error",
+                message: e as string,
+                withBorder: true,
+                color: "red",
+            });
+        }
+    }
+
     useEffect(() => {
         start_scan();
     }, []);
 
     useEffect(() => {
-        let unlisten = listen("connected", (event) => {
+        let unlisten = listen<Device>("connected", (event) => {
             info(`connected : {event}`);
+            setDevice(event.payload);
             setLoading(false);
             navigate("/connected");
         });
@@ -106,6 +125,23 @@ function Home() {
                         >
                             Connect
                         </Button>
+                        <Flex w={"100%"} gap={"sm"}>
+                            <TextInput
+                                placeholder="AA:BB:CC:DD:EE:FF"
+                                value={address}
+                                onChange={(event) => setAddress(event.currentTarget.value)}
+                                style={{ flex: 1 }}
+                            />
+                            <Button
+                                loading={loading}
+                                disabled={address.trim() === ""}
+                                color="green"
+                                variant="outline"
+                                onClick={connect_address}
+                            >
+                                Connect by address
+                            </Button>
+                        </Flex>
                     </>
                 ) : (
                     <Flex direction={"column"} gap={"md"} m={"lg"} p={"md"}>
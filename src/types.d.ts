@@ -1,4 +1,75 @@
 interface Device {
     id: string,
-    name: string
-}
\ No newline at end of file
+    name: string,
+    rssi: number | null,
+    tx_power: number | null,
+    last_seen: number,
+    services: string[],
+    alias: string | null,
+    color: string | null,
+    notes: string | null,
+    profile: string | null,
+    info: DeviceInfo | null
+}
+
+interface DeviceInfo {
+    manufacturer: string | null,
+    model: string | null,
+    serial: string | null,
+    firmware_revision: string | null,
+    hardware_revision: string | null,
+    battery: number | null
+}
+
+interface BatteryLevel {
+    id: string,
+    level: number
+}
+
+interface KnownDevice {
+    address: string,
+    alias: string | null,
+    color: string | null,
+    notes: string | null,
+    pair: number | null,
+    port: string | null,
+    profile: UuidProfile | null,
+    port_name: string | null,
+    framing: Framing | null,
+    crc_policy: BadFramePolicy | null,
+    decoder: DecoderConfig | null,
+    auto_connect: boolean
+}
+
+interface UuidProfile {
+    service_uuid: string,
+    characteristic_uuid: string
+}
+
+type Endianness = "Little" | "Big";
+
+type Framing =
+    | "Notification"
+    | { LengthPrefixed: { offset: number, width: number, endianness: Endianness, includes_header: boolean } }
+    | { Delimiter: number[] }
+    | "Slip"
+    | "Cobs";
+
+type BadFramePolicy = "Forward" | "Drop" | { Mark: number[] };
+
+interface DecoderConfig {
+    channels: number,
+    sample_width: number,
+    endianness: Endianness,
+    signed: boolean,
+    header_len: number,
+    counter_offset: number | null,
+    counter_width: number,
+    format: "Csv" | "JsonLines",
+    output: "Port" | { File: string }
+}
+
+interface FieldError {
+    field: string,
+    message: string
+}